@@ -0,0 +1,476 @@
+//! Replaces everything personal in a backup, so it can be shared to reproduce bugs
+//!
+//! Numbers and names are replaced by pseudonyms derived from a secret salt,
+//! so the same salt always gives the same pseudonyms (and conversations stay together).
+//! Bodies are scrambled one character at a time, keeping the kind of each character,
+//! so the scrambled text is the same length in bytes, characters and utf16 units.
+//! The names of attachments are replaced too (keeping their extension),
+//! along with the references to them in the SMIL that lays out an MMS.
+//! Identifiers (like MMS message ids) and attributes we don't understand are scrambled too,
+//! since there's no telling what they give away.
+
+use std::collections::BTreeMap;
+
+use base64::{engine::general_purpose::STANDARD as BASE64_ENGINE, Engine};
+use regex::{Captures, Regex};
+use sha2::{Digest, Sha256};
+
+use crate::model::{
+    MmsMessage, MmsMessagePart, PhoneNumber, SmsMessage, TextLog, SELF_ADDRESS_TOKEN,
+    UNKNOWN_CONTACT_NAME,
+};
+
+/// The longest suffix of a number that's replaced
+///
+/// Anything before this (like a country code or trunk prefix) is kept,
+/// so different ways of writing a number still refer to the same one.
+const MAX_REPLACED_DIGITS: usize = 10;
+
+/// Tiny images used in place of the real ones
+const PLACEHOLDER_PNG: &str =
+    "iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAQAAAC1HAwCAAAAC0lEQVR42mNkYAAAAAYAAjCB0C8AAAAASUVORK5CYII=";
+const PLACEHOLDER_GIF: &str = "R0lGODlhAQABAIAAAAAAAP///yH5BAEAAAAALAAAAAABAAEAAAIBRAA7";
+const PLACEHOLDER_JPEG: &str = "/9j/4AAQSkZJRgABAQEAYABgAAD/2wBDAAgGBgcGBQgHBwcJCQgKDBQNDAsLDBkSEw8UHRofHh0aHBwgJC4nICIsIxwcKDcpLDAxNDQ0Hyc5PTgyPC4zNDL/wAALCAABAAEBAREA/8QAHwAAAQUBAQEBAQEAAAAAAAAAAAECAwQFBgcICQoL/8QAtRAAAgEDAwIEAwUFBAQAAAF9AQIDAAQRBRIhMUEGE1FhByJxFDKBkaEII0KxwRVS0fAkM2JyggkKFhcYGRolJicoKSo0NTY3ODk6Q0RFRkdISUpTVFVWV1hZWmNkZWZnaGlqc3R1dnd4eXqDhIWGh4iJipKTlJWWl5iZmqKjpKWmp6ipqrKztLW2t7i5usLDxMXGx8jJytLT1NXW19jZ2uHi4+Tl5ufo6erx8vP09fb3+Pn6/9oACAEBAAA/APn+iiigD//Z";
+
+/// Derives pseudonyms and scrambled text from a secret salt
+pub struct Anonymizer {
+    salt: Vec<u8>,
+    /// Matches the attributes of a SMIL layout which refer to (or describe) a part
+    smil_reference: Regex,
+}
+impl Anonymizer {
+    pub fn new(salt: &str) -> Anonymizer {
+        Anonymizer {
+            salt: salt.as_bytes().to_vec(),
+            smil_reference: Regex::new(r#"\b(src|alt|title)\s*=\s*("[^"]*"|'[^']*')"#).unwrap(),
+        }
+    }
+    /// Anonymizes every message in the log, in place
+    pub fn anonymize_log(&self, log: &mut TextLog) {
+        for sms in &mut log.sms_messages {
+            self.anonymize_sms(sms);
+        }
+        for mms in &mut log.mms_messages {
+            self.anonymize_mms(mms);
+        }
+    }
+    fn anonymize_sms(&self, sms: &mut SmsMessage) {
+        sms.address = self.number(&sms.address);
+        sms.contact_name = self.contact_name(&sms.contact_name);
+        sms.body = self.scramble(&sms.body);
+        if let Some(subject) = &mut sms.subject {
+            *subject = self.scramble(subject);
+        }
+        if let Some(service_center) = &mut sms.service_center {
+            *service_center = self.number(&PhoneNumber::new(&**service_center)).into();
+        }
+        self.anonymize_extra(&mut sms.extra);
+    }
+    fn anonymize_mms(&self, mms: &mut MmsMessage) {
+        // Group messages have a `~` separated number for each participant
+        let address = mms
+            .address
+            .raw()
+            .split('~')
+            .map(|number| String::from(self.number(&PhoneNumber::new(number))))
+            .collect::<Vec<_>>()
+            .join("~");
+        mms.address = PhoneNumber::new(address);
+        mms.contact_name = self.contact_name(&mms.contact_name);
+        for addr in &mut mms.addrs {
+            addr.address = self.number(&addr.address);
+            self.anonymize_extra(&mut addr.extra);
+        }
+        let headers = &mut mms.headers;
+        let texts = [
+            &mut headers.sub,
+            &mut headers.retr_txt,
+            &mut headers.resp_txt,
+        ];
+        for text in texts.into_iter().flatten() {
+            *text = self.scramble(text);
+        }
+        // The message and transaction ids are shared by the copies of a message,
+        // so they're replaced by the same pseudonym each time
+        let ids = [
+            &mut headers.m_id,
+            &mut headers.tr_id,
+            &mut headers.ct_l,
+            &mut headers.creator,
+        ];
+        for id in ids.into_iter().flatten() {
+            *id = self.scramble_keyed("id", id, id);
+        }
+        self.anonymize_extra(&mut mms.extra);
+        for part in &mut mms.parts {
+            self.anonymize_part(part);
+        }
+    }
+    fn anonymize_part(&self, part: &mut MmsMessagePart) {
+        if let Some(text) = &mut part.text {
+            if part.content_type == "application/smil" {
+                *text = self.anonymize_smil(text);
+            } else if part.content_type == "text/plain" {
+                *text = self.scramble(text);
+            }
+        }
+        if let Some(data) = &mut part.data {
+            *data = placeholder(&part.content_type, data.len());
+        }
+        part.content_location = self.file_name(&part.content_location);
+        let names = [&mut part.name, &mut part.filename, &mut part.content_id];
+        for name in names.into_iter().flatten() {
+            *name = self.file_name(name);
+        }
+        self.anonymize_extra(&mut part.extra);
+    }
+    /// Scrambles the attributes we don't understand, since they could be anything
+    fn anonymize_extra(&self, extra: &mut BTreeMap<String, String>) {
+        for (key, value) in extra {
+            if value != "null" {
+                *value = self.scramble_keyed("extra", &format!("{}={}", key, value), value);
+            }
+        }
+    }
+    /// Replaces the name of a file (or content id) with a pseudonym, keeping its extension
+    ///
+    /// The same name always gets the same pseudonym,
+    /// so the references to a part from the SMIL still find it.
+    pub fn file_name(&self, name: &str) -> String {
+        if name.is_empty() || name == "null" {
+            return name.to_owned();
+        }
+        // Content ids are usually wrapped in angle brackets, but the references to them aren't
+        if let Some(inner) = name
+            .strip_prefix('<')
+            .and_then(|name| name.strip_suffix('>'))
+        {
+            return format!("<{}>", self.file_name(inner));
+        }
+        let hash = crate::utils::to_hex(&self.hash("file", name)[..4]);
+        match name.rsplit_once('.') {
+            Some((_, extension))
+                if (1..=5).contains(&extension.len())
+                    && extension.bytes().all(|b| b.is_ascii_alphanumeric()) =>
+            {
+                format!("file_{}.{}", hash, extension)
+            }
+            _ => format!("file_{}", hash),
+        }
+    }
+    /// Replaces the names in a SMIL layout, which refer to parts by their name or content id
+    fn anonymize_smil(&self, smil: &str) -> String {
+        self.smil_reference
+            .replace_all(smil, |captures: &Captures<'_>| {
+                let value = &captures[2];
+                let (quote, value) = (&value[..1], &value[1..value.len() - 1]);
+                let value = match value.strip_prefix("cid:") {
+                    Some(content_id) => format!("cid:{}", self.file_name(content_id)),
+                    None => self.file_name(value),
+                };
+                format!("{}={}{}{}", &captures[1], quote, value, quote)
+            })
+            .into_owned()
+    }
+    /// Replaces the number with a pseudonym that's written the same way
+    ///
+    /// Numbers that are the same once normalized get the same pseudonym,
+    /// and so do their normalized forms.
+    pub fn number(&self, number: &PhoneNumber) -> PhoneNumber {
+        let raw = number.raw();
+        if raw.is_empty() || raw == SELF_ADDRESS_TOKEN {
+            return number.clone();
+        }
+        let is_number = raw
+            .chars()
+            .all(|c| c.is_ascii_digit() || matches!(c, ' ' | '-' | '.' | '(' | ')' | '/' | '+'));
+        if !is_number {
+            // Alphanumeric sender ids, emails, etc
+            return PhoneNumber::new(self.scramble_keyed("address", raw, raw));
+        }
+        let digits = raw.chars().filter(char::is_ascii_digit).count();
+        let mut stream = self.stream("number", number.normalized());
+        let keep = digits.saturating_sub(MAX_REPLACED_DIGITS);
+        let mut seen = 0;
+        let pseudonym = raw
+            .chars()
+            .map(|c| {
+                if !c.is_ascii_digit() {
+                    return c;
+                }
+                seen += 1;
+                if seen <= keep {
+                    c
+                } else if seen == keep + 1 {
+                    // Never start with a trunk prefix, which would change how it's normalized
+                    char::from(b'2' + stream.below(8) as u8)
+                } else {
+                    char::from(b'0' + stream.below(10) as u8)
+                }
+            })
+            .collect::<String>();
+        PhoneNumber::new(pseudonym)
+    }
+    /// Replaces each name (of a group) with a pseudonym
+    pub fn contact_name(&self, name: &str) -> String {
+        if name == UNKNOWN_CONTACT_NAME || name.is_empty() {
+            return name.to_owned();
+        }
+        name.split(", ")
+            .map(|name| {
+                let hash = self.hash("name", name);
+                format!("Contact {}", crate::utils::to_hex(&hash[..3]))
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+    /// Scrambles the text, replacing each character with one of the same kind
+    pub fn scramble(&self, text: &str) -> String {
+        self.scramble_keyed("text", text, text)
+    }
+    fn scramble_keyed(&self, domain: &str, key: &str, text: &str) -> String {
+        let mut stream = self.stream(domain, key);
+        text.chars()
+            .map(|c| scramble_char(c, &mut stream))
+            .collect()
+    }
+    fn hash(&self, domain: &str, value: &str) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(&self.salt);
+        hasher.update([0]);
+        hasher.update(domain);
+        hasher.update([0]);
+        hasher.update(value);
+        hasher.finalize().into()
+    }
+    fn stream(&self, domain: &str, value: &str) -> Stream {
+        Stream {
+            seed: self.hash(domain, value),
+            counter: 0,
+            block: [0; 32],
+            index: 32,
+        }
+    }
+}
+
+/// Replaces a character with a random one of the same kind
+///
+/// Letters (and digits) are replaced by another of the same case from the same block of 128
+/// code points, so they stay in the same script, and keep their length in utf8 and utf16.
+/// Emoticons are replaced by other emoticons.
+/// Whitespace, punctuation, symbols and the invisible characters that hold emoji sequences
+/// together are kept, so the text keeps its shape.
+fn scramble_char(c: char, stream: &mut Stream) -> char {
+    let pick = |stream: &mut Stream, first: u32, count: u32| {
+        char::from_u32(first + stream.below(count)).unwrap()
+    };
+    match c {
+        'a'..='z' => pick(stream, 'a' as u32, 26),
+        'A'..='Z' => pick(stream, 'A' as u32, 26),
+        '0'..='9' => pick(stream, '0' as u32, 10),
+        _ if c.is_ascii() => c,
+        // Combining marks, zero-width joiners and variation selectors
+        '\u{300}'..='\u{36F}' | '\u{200B}'..='\u{200F}' | '\u{FE00}'..='\u{FE0F}' => c,
+        '\u{1F600}'..='\u{1F64F}' => pick(stream, 0x1F600, 0x50),
+        _ if c.is_alphabetic() || c.is_numeric() => {
+            let same_kind = |other: &char| {
+                other.is_alphabetic() == c.is_alphabetic()
+                    && other.is_numeric() == c.is_numeric()
+                    && other.is_lowercase() == c.is_lowercase()
+                    && other.is_uppercase() == c.is_uppercase()
+            };
+            let block = c as u32 & !0x7F;
+            // Always includes `c` itself
+            let candidates = (block..block + 0x80)
+                .filter_map(char::from_u32)
+                .filter(same_kind)
+                .collect::<Vec<_>>();
+            candidates[stream.below(candidates.len() as u32) as usize]
+        }
+        _ => c,
+    }
+}
+
+/// Data with the same content type, which says nothing about the original
+///
+/// Images are replaced by a blank pixel, and anything else by zeros of the same length.
+fn placeholder(content_type: &str, len: usize) -> Vec<u8> {
+    let image = match content_type {
+        "image/png" => PLACEHOLDER_PNG,
+        "image/gif" => PLACEHOLDER_GIF,
+        "image/jpeg" | "image/jpg" => PLACEHOLDER_JPEG,
+        _ => return vec![0; len],
+    };
+    BASE64_ENGINE.decode(image).unwrap()
+}
+
+/// A deterministic stream of random numbers, from hashing a seed with a counter
+struct Stream {
+    seed: [u8; 32],
+    counter: u64,
+    block: [u8; 32],
+    index: usize,
+}
+impl Stream {
+    fn next_u32(&mut self) -> u32 {
+        if self.index + 4 > self.block.len() {
+            let mut hasher = Sha256::new();
+            hasher.update(self.seed);
+            hasher.update(self.counter.to_le_bytes());
+            self.block = hasher.finalize().into();
+            self.counter += 1;
+            self.index = 0;
+        }
+        let bytes = self.block[self.index..self.index + 4].try_into().unwrap();
+        self.index += 4;
+        u32::from_le_bytes(bytes)
+    }
+    /// A number less than `limit` (with negligible bias)
+    #[inline]
+    fn below(&mut self, limit: u32) -> u32 {
+        self.next_u32() % limit
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::model::{BodyKind, PhoneNumber, TextMessage};
+    use crate::xml::testing::{log, received};
+
+    use super::{scramble_char, Anonymizer};
+
+    #[test]
+    fn test_scramble_scripts() {
+        let mut stream = Anonymizer::new("secret").stream("text", "");
+        for _ in 0..100 {
+            let scrambled = "Привет — €5, Ærø ÷ 東京 😊"
+                .chars()
+                .map(|c| scramble_char(c, &mut stream))
+                .collect::<String>();
+            let chars = scrambled.chars().collect::<Vec<_>>();
+            assert!(chars[0].is_uppercase(), "{}", scrambled);
+            for &c in &chars[..6] {
+                assert!(('\u{400}'..='\u{47F}').contains(&c), "{}", scrambled);
+            }
+            assert_eq!(chars[1..6].iter().filter(|c| c.is_lowercase()).count(), 5);
+            assert_eq!(&scrambled[12..20], " — €", "{}", scrambled);
+            for c in [chars[13], chars[15]] {
+                assert!(
+                    c.is_alphabetic() && ('\u{80}'..='\u{FF}').contains(&c),
+                    "{}",
+                    scrambled
+                );
+            }
+            assert!(chars[13].is_uppercase() && chars[15].is_lowercase());
+            assert_eq!(chars[17], '÷');
+            assert!(chars[19..21]
+                .iter()
+                .all(|&c| ('\u{4E00}'..='\u{9FFF}').contains(&c)));
+            assert!(('\u{1F600}'..='\u{1F64F}').contains(&chars[22]));
+        }
+    }
+
+    #[test]
+    fn test_anonymize() {
+        let mut log = log([
+            r#"<sms address="+15551234567" date="1533000000000" type="2" body="Hey Alice &#55357;&#56842; caf&#233; 42!" readable_date="" contact_name="Alice" sender_name="Alice Smith" />"#.to_owned(),
+            received("AMAZON", "(Unknown)", "2018-07-31 01:21", "Your code is 1234"),
+            // Identifiers and attributes we don't understand are personal too
+            r#"<mms address="+1 (555) 123-4567~+15559876543" date="1533000120000" msg_box="1" date_sent="0" readable_date="" contact_name="Alice, Bob" m_id="Bob-5559876543" tr_id="T5559876543" ct_l="http://mms.example.com/Bob5559876543" retr_txt="Sent by Bob" resp_txt="For Alice" creator="com.bob.messages" group_name="Alice and Bob">
+    <parts>
+      <part seq="0" ct="image/png" cl="a.png" text="null" data="AAAAAAAA" />
+      <part seq="0" ct="text/plain" cl="text_0.txt" text="Look" />
+    </parts>
+    <addrs>
+      <addr address="+15559876543" type="137" charset="106" display_name="Bob" />
+    </addrs>
+  </mms>"#.to_owned(),
+            r#"<mms address="+15559876543" date="1533000180000" msg_box="1" date_sent="0" readable_date="" contact_name="Bob">
+    <parts>
+      <part seq="-1" ct="application/smil" cl="smil.xml" text='&lt;smil&gt;&lt;body&gt;&lt;par dur="5000ms"&gt;&lt;img src="IMG_Alice_birthday_party.jpg" region="Image" /&gt;&lt;ref src="cid:Alice_card" alt="Alice Smith.vcf" /&gt;&lt;/par&gt;&lt;/body&gt;&lt;/smil&gt;' />
+      <part seq="0" ct="image/jpeg" name="IMG_Alice_birthday_party.jpg" chset="null" cd="null" fn="IMG_Alice_birthday_party.jpg" cid="&lt;IMG_Alice_birthday_party&gt;" cl="IMG_Alice_birthday_party.jpg" ctt_s="null" ctt_t="null" text="null" data="AAAAAAAA" caption="Alice at her party" />
+      <part seq="0" ct="text/x-vCard" name="Alice Smith.vcf" cid="&lt;Alice_card&gt;" cl="Alice Smith.vcf" text="null" data="AAAAAAAA" />
+    </parts>
+  </mms>"#.to_owned(),
+        ]);
+        let original = log.clone();
+        let anonymizer = Anonymizer::new("secret");
+        anonymizer.anonymize_log(&mut log);
+        let mut xml = Vec::new();
+        crate::xml::write_log(&log, &mut xml).unwrap();
+        let log = crate::xml::parse_log(false, false, &*xml).unwrap();
+
+        let (sms, old_sms) = (&log.sms_messages[0], &original.sms_messages[0]);
+        assert_ne!(sms.address, old_sms.address);
+        assert_ne!(sms.contact_name, "Alice");
+        assert_ne!(sms.body, old_sms.body);
+        assert_eq!(sms.body.len(), old_sms.body.len());
+        assert_eq!(sms.body.chars().count(), old_sms.body.chars().count());
+        assert_eq!(
+            sms.body.encode_utf16().count(),
+            old_sms.body.encode_utf16().count()
+        );
+        assert!(sms.body.chars().nth(10).unwrap() >= '\u{10000}');
+        assert_eq!(sms.body.chars().nth(3), Some(' '));
+        assert!(sms.body.ends_with('!'));
+        assert_eq!(log.sms_messages[1].contact_name, "(Unknown)");
+        assert_ne!(log.sms_messages[1].address.raw(), "AMAZON");
+
+        // The same people, however their numbers were written, are still the same
+        let mms = &log.mms_messages[0];
+        let participants = mms.participants();
+        assert_eq!(participants[0], sms.address);
+        assert_eq!(mms.sender(), Some(&participants[1]));
+        assert_eq!(
+            mms.contact_name,
+            format!("{}, {}", sms.contact_name, anonymizer.contact_name("Bob"))
+        );
+        assert_eq!(log.conversations().len(), original.conversations().len());
+        let BodyKind::Mms { parts } = mms.body() else {
+            unreachable!()
+        };
+        assert!(parts[0].data.as_ref().unwrap().starts_with(b"\x89PNG"));
+        assert_eq!(parts[1].text.as_ref().unwrap().len(), 4);
+
+        // Identifiers keep their shape
+        let headers = &mms.headers;
+        assert_eq!(headers.m_id.as_ref().unwrap().len(), "Bob-5559876543".len());
+        assert_eq!(headers.creator.as_ref().unwrap().matches('.').count(), 2);
+
+        // Nothing is left of the names of attachments, but the SMIL still refers to them
+        let xml = String::from_utf8(xml).unwrap();
+        for personal in [
+            "Alice",
+            "Bob",
+            "AMAZON",
+            "party",
+            "5551234567",
+            "555) 123-4567",
+            "5559876543",
+            "example",
+            "bob",
+        ] {
+            assert!(!xml.contains(personal), "{:?} in {}", personal, xml);
+        }
+        let parts = &log.mms_messages[1].parts;
+        assert!(parts[1].content_location.ends_with(".jpg"));
+        assert_eq!(parts[1].name.as_ref(), Some(&parts[1].content_location));
+        let smil = parts[0].text.as_ref().unwrap();
+        assert!(
+            smil.contains(&format!("src=\"{}\"", parts[1].content_location)),
+            "{}",
+            smil
+        );
+        let content_id = parts[2].content_id.as_ref().unwrap();
+        assert!(smil.contains(&format!(
+            "src=\"cid:{}\"",
+            &content_id[1..content_id.len() - 1]
+        )));
+        assert!(smil.contains(&format!("alt=\"{}\"", parts[2].content_location)));
+
+        // Stable for the same salt, but not for a different one
+        let number = PhoneNumber::new("+15551234567");
+        assert_eq!(anonymizer.number(&number), sms.address);
+        assert_ne!(Anonymizer::new("other").number(&number), sms.address);
+    }
+}
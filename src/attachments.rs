@@ -0,0 +1,177 @@
+//! Extracts MMS attachments into a directory, along with a manifest
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, BufWriter};
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::model::{MmsMessage, MmsMessagePart, PhoneNumber, TextLog, UNKNOWN_CONTACT_NAME};
+
+/// The name of the manifest, written alongside the attachments
+pub const MANIFEST_NAME: &str = "manifest.json";
+
+/// The longest we let any one component of a file name get
+const MAX_COMPONENT_LEN: usize = 40;
+
+/// Maps an extracted file back to the message it came from
+#[derive(Debug, Serialize)]
+pub struct ManifestEntry {
+    /// The name of the file, relative to the output directory
+    pub file: String,
+    /// The SHA-256 of the contents
+    pub sha256: String,
+    /// The date of the message
+    pub date: DateTime<Utc>,
+    /// The address of the message
+    pub address: PhoneNumber,
+    pub contact_name: String,
+    pub content_type: String,
+    pub content_location: String,
+    pub seq: i32,
+}
+
+/// The result of extracting attachments
+#[derive(Debug, Default)]
+pub struct Extracted {
+    pub manifest: Vec<ManifestEntry>,
+    /// The number of files actually written
+    ///
+    /// This is less than the number of entries in the manifest,
+    /// since identical attachments share a single file.
+    pub files: usize,
+}
+
+/// Writes every part (except the SMIL) of every MMS to `output`,
+/// followed by a [manifest](MANIFEST_NAME)
+pub fn extract(log: &TextLog, output: &Path) -> io::Result<Extracted> {
+    fs::create_dir_all(output)?;
+    let mut messages = log.mms_messages.iter().collect::<Vec<_>>();
+    messages.sort_by_key(|mms| mms.date);
+    let mut by_hash = HashMap::<String, String>::new();
+    let mut used_names = HashSet::<String>::new();
+    let mut result = Extracted::default();
+    for mms in messages {
+        for part in &mms.parts {
+            if part.content_type == "application/smil" {
+                continue;
+            }
+            let Some(contents) = part_contents(part) else {
+                continue;
+            };
+            let sha256 = crate::utils::to_hex(&Sha256::digest(contents));
+            let file = match by_hash.get(&sha256) {
+                Some(existing) => existing.clone(),
+                None => {
+                    let name = unique_name(&file_name(mms, part), &mut used_names);
+                    fs::write(output.join(&name), contents)?;
+                    result.files += 1;
+                    by_hash.insert(sha256.clone(), name.clone());
+                    name
+                }
+            };
+            result.manifest.push(ManifestEntry {
+                file,
+                sha256,
+                date: mms.date,
+                address: mms.address.clone(),
+                contact_name: mms.contact_name.clone(),
+                content_type: part.content_type.clone(),
+                content_location: part.content_location.clone(),
+                seq: part.seq,
+            });
+        }
+    }
+    let manifest = BufWriter::new(fs::File::create(output.join(MANIFEST_NAME))?);
+    serde_json::to_writer_pretty(manifest, &result.manifest)?;
+    Ok(result)
+}
+
+/// The raw contents of a part, which is either binary data or text
+fn part_contents(part: &MmsMessagePart) -> Option<&[u8]> {
+    part.data
+        .as_deref()
+        .or_else(|| part.text.as_deref().map(str::as_bytes))
+}
+
+/// Names the file for a part, like `2018-07-31_013000_Alice_image000000_0.jpg`
+fn file_name(mms: &MmsMessage, part: &MmsMessagePart) -> String {
+    let contact = match &*mms.contact_name {
+        UNKNOWN_CONTACT_NAME | "" => mms.address.normalized(),
+        name => name,
+    };
+    // The location usually has an extension of its own, which may not match the content type
+    let location = part
+        .content_location
+        .rsplit_once('.')
+        .map_or(&*part.content_location, |(stem, _)| stem);
+    format!(
+        "{}_{}_{}_{}.{}",
+        mms.date.format("%Y-%m-%d_%H%M%S"),
+        sanitize_component(contact),
+        sanitize_component(location),
+        part.seq,
+        part.extension()
+    )
+}
+
+/// Replaces anything that's not safe in a file name
+fn sanitize_component(text: &str) -> String {
+    let sanitized = text
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '+' => c,
+            _ => '_',
+        })
+        .take(MAX_COMPONENT_LEN)
+        .collect::<String>();
+    if sanitized.is_empty() {
+        "unnamed".into()
+    } else {
+        sanitized
+    }
+}
+
+/// Appends a counter to the name if it's already been used by different contents
+fn unique_name(name: &str, used: &mut HashSet<String>) -> String {
+    if used.insert(name.to_owned()) {
+        return name.to_owned();
+    }
+    let (stem, extension) = name.rsplit_once('.').unwrap_or((name, ""));
+    (1..)
+        .map(|counter| format!("{}-{}.{}", stem, counter, extension))
+        .find(|candidate| used.insert(candidate.clone()))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use super::{file_name, unique_name};
+    use crate::xml::testing::{data_part, log, received_mms};
+
+    #[test]
+    fn test_file_names() {
+        let part = data_part("image/jpeg", "IMG 0001.JPG", "AAAA");
+        let log = log([received_mms(
+            "+15551234567",
+            "Alice / Work",
+            "2018-07-31 01:21",
+            &[part],
+            &[],
+        )]);
+        let mms = &log.mms_messages[0];
+        let name = file_name(mms, &mms.parts[0]);
+        assert_eq!(name, "2018-07-31_012100_Alice___Work_IMG_0001_0.jpg");
+        let mut used = HashSet::new();
+        assert_eq!(unique_name(&name, &mut used), name);
+        assert_eq!(
+            unique_name(&name, &mut used),
+            "2018-07-31_012100_Alice___Work_IMG_0001_0-1.jpg"
+        );
+    }
+}
@@ -0,0 +1,114 @@
+//! Keeps attachment data out of JSON dumps,
+//! storing it in a directory of files named by their hash
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::model::TextLog;
+
+/// The name of the blob directory, which sits next to the JSON dump
+pub const BLOBS_DIR: &str = "blobs";
+
+/// Refers to the data of a part, stored in the blob directory
+///
+/// The content type is the part's own.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlobRef {
+    /// The SHA-256 of the data, which is also the name of the file
+    pub sha256: String,
+    /// The length of the data in bytes
+    pub size: u64,
+}
+
+/// Moves the data of every MMS part into blobs under `dir`,
+/// leaving a [BlobRef] in its place
+///
+/// Returns the number of new blobs written,
+/// which excludes any that were already there.
+pub fn store(log: &mut TextLog, dir: &Path) -> io::Result<usize> {
+    let blobs = dir.join(BLOBS_DIR);
+    fs::create_dir_all(&blobs)?;
+    let mut written = 0;
+    for part in log.mms_messages.iter_mut().flat_map(|mms| &mut mms.parts) {
+        let Some(data) = part.data.take() else {
+            continue;
+        };
+        let sha256 = crate::utils::to_hex(&Sha256::digest(&data));
+        let path = blobs.join(&sha256);
+        if !path.exists() {
+            fs::write(path, &data)?;
+            written += 1;
+        }
+        part.blob = Some(BlobRef {
+            sha256,
+            size: data.len() as u64,
+        });
+    }
+    Ok(written)
+}
+
+/// Reads back the data of every part that refers to a blob under `dir`
+///
+/// This checks that the blob matches its hash.
+pub fn load(log: &mut TextLog, dir: &Path) -> io::Result<()> {
+    let blobs = dir.join(BLOBS_DIR);
+    for part in log.mms_messages.iter_mut().flat_map(|mms| &mut mms.parts) {
+        let Some(blob) = part.blob.take() else {
+            continue;
+        };
+        let path = blobs.join(&blob.sha256);
+        let data = fs::read(&path).map_err(|cause| {
+            io::Error::new(
+                cause.kind(),
+                format!("Unable to read blob {}: {}", path.display(), cause),
+            )
+        })?;
+        if data.len() as u64 != blob.size
+            || crate::utils::to_hex(&Sha256::digest(&data)) != blob.sha256
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Blob {} doesn't match its hash", path.display()),
+            ));
+        }
+        part.data = Some(data);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use crate::model::TextLog;
+    use crate::xml::testing::{data_part, log, received_mms};
+
+    #[test]
+    fn test_roundtrip() {
+        let dir = std::env::temp_dir().join(format!("smstools-blobs-{}", std::process::id()));
+        // The same image twice
+        let parts = [
+            data_part("image/png", "a.png", "iVBORw0KGgo="),
+            data_part("image/png", "b.png", "iVBORw0KGgo="),
+        ];
+        let original = log([received_mms(
+            "+15551234567",
+            "Alice",
+            "2018-07-31 01:21",
+            &parts,
+            &[],
+        )]);
+        let mut log = original.clone();
+        assert_eq!(super::store(&mut log, &dir).unwrap(), 1);
+        let json = serde_json::to_string(&log).unwrap();
+        assert!(!json.contains("iVBORw0KGgo"));
+        let mut loaded: TextLog = serde_json::from_str(&json).unwrap();
+        super::load(&mut loaded, &dir).unwrap();
+        assert_eq!(loaded.mms_messages, original.mms_messages);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,198 @@
+//! Draws the activity in a conversation as inline SVG
+//!
+//! These don't need any scripts, so they work in a page opened straight from disk.
+
+use std::collections::BTreeMap;
+
+use chrono::{Datelike, NaiveDate};
+use maud::{html, Markup};
+
+use crate::dates::DateStyle;
+use crate::model::{Conversation, MessageKind};
+
+/// The size of each day in the heatmap, including the space around it
+const CELL: u32 = 13;
+/// Room for the labels on the left and top of the heatmap
+const HEATMAP_MARGIN: u32 = 30;
+/// The colors of the heatmap, from no messages up to the busiest days
+const HEAT_COLORS: [&str; 5] = ["#ebedf0", "#c6dbef", "#6baed6", "#2171b5", "#08306b"];
+const SENT_COLOR: &str = "#05728f";
+const RECEIVED_COLOR: &str = "#9aa5ab";
+/// The width given to each month in the bar chart
+const MONTH_WIDTH: u32 = 16;
+const BAR_WIDTH: u32 = 6;
+const BAR_HEIGHT: u32 = 100;
+
+/// The number of messages sent and received in some period
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+struct Counts {
+    sent: u32,
+    received: u32,
+}
+impl Counts {
+    #[inline]
+    fn total(self) -> u32 {
+        self.sent + self.received
+    }
+}
+
+/// Summarizes a conversation with a heatmap of every day,
+/// and the number of messages sent and received each month
+pub fn render_summary(conversation: &Conversation<'_>, style: &DateStyle) -> Markup {
+    let mut days = BTreeMap::<NaiveDate, Counts>::new();
+    for &message in &conversation.messages {
+        let date = style
+            .localize(message.date(), message.readable_date())
+            .date_naive();
+        let counts = days.entry(date).or_default();
+        match message.kind() {
+            MessageKind::Received { .. } => counts.received += 1,
+            // Drafts and the like count as ours
+            _ => counts.sent += 1,
+        }
+    }
+    let mut months = BTreeMap::<(i32, u32), Counts>::new();
+    for (day, counts) in &days {
+        let month = months.entry((day.year(), day.month())).or_default();
+        month.sent += counts.sent;
+        month.received += counts.received;
+    }
+    html! {
+        div class="container activity_summary" {
+            @if let (Some(first), Some(last)) = (days.keys().next(), days.keys().next_back()) {
+                @for year in (first.year()..=last.year()).rev() {
+                    (render_heatmap(year, &days))
+                }
+                (render_monthly_bars(&months))
+            }
+        }
+    }
+}
+
+/// Draws a calendar of the year, with a column for each week
+fn render_heatmap(year: i32, days: &BTreeMap<NaiveDate, Counts>) -> Markup {
+    let busiest = days
+        .values()
+        .map(|counts| counts.total())
+        .max()
+        .unwrap_or(0);
+    let first = NaiveDate::from_ymd_opt(year, 1, 1).unwrap();
+    let offset = first.weekday().num_days_from_monday();
+    let width = HEATMAP_MARGIN + 54 * CELL;
+    let height = HEATMAP_MARGIN + 7 * CELL;
+    html! {
+        svg xmlns="http://www.w3.org/2000/svg" width=(width) height=(height) class="heatmap" {
+            text x="0" y="12" font-size="12" font-weight="bold" { (year) }
+            @for (row, name) in [(0, "Mon"), (2, "Wed"), (4, "Fri")] {
+                text x="0" y=(HEATMAP_MARGIN + row * CELL + 9) font-size="9" fill="#747474" { (name) }
+            }
+            @for day in first.iter_days().take_while(|day| day.year() == year) {
+                @let column = (day.ordinal0() + offset) / 7;
+                @let row = day.weekday().num_days_from_monday();
+                @let count = days.get(&day).map_or(0, |counts| counts.total());
+                @if day.day() == 1 {
+                    text x=(HEATMAP_MARGIN + column * CELL) y=(HEATMAP_MARGIN - 4) font-size="9" fill="#747474" {
+                        (day.format("%b"))
+                    }
+                }
+                rect x=(HEATMAP_MARGIN + column * CELL) y=(HEATMAP_MARGIN + row * CELL)
+                    width=(CELL - 2) height=(CELL - 2) fill=(heat_color(count, busiest)) {
+                    title { (day.format("%Y-%m-%d")) ": " (count) " messages" }
+                }
+            }
+        }
+    }
+}
+
+/// The color of a day, relative to the busiest day
+fn heat_color(count: u32, busiest: u32) -> &'static str {
+    if count == 0 {
+        return HEAT_COLORS[0];
+    }
+    let levels = (HEAT_COLORS.len() - 1) as u32;
+    HEAT_COLORS[count
+        .saturating_mul(levels)
+        .div_ceil(busiest)
+        .clamp(1, levels) as usize]
+}
+
+/// Draws a pair of bars for each month, including any months without messages
+fn render_monthly_bars(months: &BTreeMap<(i32, u32), Counts>) -> Markup {
+    let (Some(&first), Some(&last)) = (months.keys().next(), months.keys().next_back()) else {
+        return html! {};
+    };
+    let all_months = std::iter::successors(Some(first), |&(year, month)| {
+        Some(if month == 12 {
+            (year + 1, 1)
+        } else {
+            (year, month + 1)
+        })
+    })
+    .take_while(|&month| month <= last)
+    .collect::<Vec<_>>();
+    let busiest = months
+        .values()
+        .map(|counts| counts.sent.max(counts.received))
+        .max()
+        .unwrap_or(0)
+        .max(1);
+    let bar = |count: u32| count * BAR_HEIGHT / busiest;
+    let width = all_months.len() as u32 * MONTH_WIDTH;
+    let height = BAR_HEIGHT + 30;
+    html! {
+        svg xmlns="http://www.w3.org/2000/svg" width=(width) height=(height) class="monthly_bars" {
+            @for (index, &(year, month)) in all_months.iter().enumerate() {
+                @let counts = months.get(&(year, month)).copied().unwrap_or_default();
+                @let x = index as u32 * MONTH_WIDTH;
+                g {
+                    title { (year) "-" (format!("{:02}", month)) ": " (counts.sent) " sent, " (counts.received) " received" }
+                    rect x=(x + 1) y=(BAR_HEIGHT - bar(counts.sent)) width=(BAR_WIDTH)
+                        height=(bar(counts.sent)) fill=(SENT_COLOR) {}
+                    rect x=(x + 1 + BAR_WIDTH) y=(BAR_HEIGHT - bar(counts.received)) width=(BAR_WIDTH)
+                        height=(bar(counts.received)) fill=(RECEIVED_COLOR) {}
+                }
+                @if month == 1 || index == 0 {
+                    text x=(x) y=(BAR_HEIGHT + 14) font-size="9" fill="#747474" { (year) }
+                }
+            }
+            rect x="0" y=(BAR_HEIGHT + 20) width="8" height="8" fill=(SENT_COLOR) {}
+            text x="12" y=(BAR_HEIGHT + 28) font-size="9" { "Sent" }
+            rect x="50" y=(BAR_HEIGHT + 20) width="8" height="8" fill=(RECEIVED_COLOR) {}
+            text x="62" y=(BAR_HEIGHT + 28) font-size="9" { "Received" }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::dates::{DateStyle, DisplayTimezone};
+    use crate::xml::testing::{log, received, sent};
+
+    #[test]
+    fn test_summary() {
+        let log = log([
+            sent("+15551234567", "Alice", "2018-07-31 01:20", "Hi"),
+            received("+15551234567", "Alice", "2018-07-31 01:21", "Hello"),
+            received(
+                "+15551234567",
+                "Alice",
+                "2019-01-01 00:00",
+                "Happy new year",
+            ),
+        ]);
+        let style = DateStyle {
+            timezone: DisplayTimezone::Named(chrono_tz::UTC),
+            ..Default::default()
+        };
+        let svg = super::render_summary(&log.conversations()[0], &style).into_string();
+        // A heatmap for each year, then the bars
+        assert_eq!(svg.matches("<svg").count(), 3);
+        assert!(svg.contains("<title>2018-07-31: 2 messages</title>"));
+        assert!(svg.contains("<title>2019-01-01: 1 messages</title>"));
+        // July 2018 to January 2019
+        assert!(svg.contains("<title>2018-07: 1 sent, 1 received</title>"));
+        assert!(svg.contains("<title>2018-10: 0 sent, 0 received</title>"));
+        assert_eq!(svg.matches("<g>").count(), 7);
+        assert!(!svg.contains("<script"));
+    }
+}
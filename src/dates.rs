@@ -0,0 +1,152 @@
+//! How dates are displayed
+
+use std::fmt::{self, Display};
+
+use chrono::format::StrftimeItems;
+use chrono::{DateTime, FixedOffset, Local, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use chrono_tz::Tz;
+
+/// The formats `readable_date` has been seen in, which depend on the phone's locale
+const READABLE_DATE_FORMATS: &[&str] = &[
+    "%b %d, %Y %I:%M:%S %p",
+    "%b %d, %Y %H:%M:%S",
+    "%d %b %Y %I:%M:%S %p",
+    "%d %b %Y %H:%M:%S",
+    "%m/%d/%Y %I:%M:%S %p",
+    "%d/%m/%Y %H:%M:%S",
+    "%d.%m.%Y %H:%M:%S",
+    "%Y-%m-%d %H:%M:%S",
+];
+
+/// The timezone dates are displayed in
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum DisplayTimezone {
+    /// The timezone of this computer
+    #[default]
+    Local,
+    /// Whatever offset the phone was in at the time,
+    /// as implied by the `readable_date` of each message
+    Backup,
+    Named(Tz),
+}
+impl Display for DisplayTimezone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DisplayTimezone::Local => f.write_str("local"),
+            DisplayTimezone::Backup => f.write_str("backup"),
+            DisplayTimezone::Named(tz) => Display::fmt(tz, f),
+        }
+    }
+}
+
+/// Controls how dates and times are displayed
+#[derive(clap::Args, Clone, Debug, Default)]
+pub struct DateStyle {
+    /// The timezone to show dates in: an IANA name (like Europe/Paris), `local` or `backup`
+    ///
+    /// `backup` uses whatever timezone the phone was in at the time,
+    /// as implied by the human-readable date the app writes with each message.
+    #[arg(long, global = true, default_value_t, value_parser = parse_timezone)]
+    pub timezone: DisplayTimezone,
+    /// The format of dates, like `%A %B %e %Y` (see the strftime syntax supported by chrono)
+    #[arg(long, global = true, value_parser = parse_format)]
+    pub date_format: Option<String>,
+    /// The format of times (defaults to `%-I:%M %p`, or `%H:%M` with `--24-hour`)
+    #[arg(long, global = true, value_parser = parse_format)]
+    pub time_format: Option<String>,
+    /// Show times on a 24-hour clock
+    #[arg(long = "24-hour", global = true)]
+    pub hour24: bool,
+}
+impl DateStyle {
+    /// Converts the date into the display timezone
+    ///
+    /// The `readable_date` is only needed for [DisplayTimezone::Backup].
+    pub fn localize(&self, date: DateTime<Utc>, readable_date: &str) -> DateTime<FixedOffset> {
+        match self.timezone {
+            DisplayTimezone::Local => date.with_timezone(&Local).fixed_offset(),
+            DisplayTimezone::Named(tz) => date.with_timezone(&tz).fixed_offset(),
+            DisplayTimezone::Backup => match backup_offset(date, readable_date) {
+                Some(offset) => date.with_timezone(&offset),
+                None => date.with_timezone(&Local).fixed_offset(),
+            },
+        }
+    }
+    pub fn format_date(&self, date: DateTime<FixedOffset>) -> String {
+        date.format(self.date_format.as_deref().unwrap_or("%A %B %e %Y"))
+            .to_string()
+    }
+    /// Formats a day that's already in the display timezone
+    ///
+    /// There's no offset to go with it, so any offset in the format is shown as UTC.
+    pub fn format_day(&self, day: NaiveDate) -> String {
+        self.format_date(day.and_time(NaiveTime::MIN).and_utc().fixed_offset())
+    }
+    pub fn format_time(&self, date: DateTime<FixedOffset>) -> String {
+        let default = if self.hour24 { "%H:%M" } else { "%-I:%M %p" };
+        date.format(self.time_format.as_deref().unwrap_or(default))
+            .to_string()
+    }
+}
+
+fn parse_timezone(text: &str) -> Result<DisplayTimezone, String> {
+    match text {
+        "local" => Ok(DisplayTimezone::Local),
+        "backup" => Ok(DisplayTimezone::Backup),
+        _ => text
+            .parse::<Tz>()
+            .map(DisplayTimezone::Named)
+            .map_err(|_| format!("Unknown timezone {:?}", text)),
+    }
+}
+/// Checks the format is valid, since chrono would otherwise fail while formatting
+fn parse_format(text: &str) -> Result<String, String> {
+    StrftimeItems::new(text)
+        .parse()
+        .map(|_| text.to_owned())
+        .map_err(|_| format!("Invalid date format {:?}", text))
+}
+
+/// The offset the phone was in, from the difference between `readable_date` and `date`
+fn backup_offset(date: DateTime<Utc>, readable_date: &str) -> Option<FixedOffset> {
+    const QUARTER_HOUR: i64 = 15 * 60;
+    // Newer versions of Android put a narrow no-break space before AM/PM
+    let readable_date = readable_date.trim().replace(['\u{202F}', '\u{A0}'], " ");
+    let local = READABLE_DATE_FORMATS
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(&readable_date, format).ok())?;
+    let seconds = (local - date.naive_utc()).num_seconds();
+    // Every real offset is a multiple of 15 minutes, which also hides the missing milliseconds
+    let rounded = (seconds as f64 / QUARTER_HOUR as f64).round() as i64 * QUARTER_HOUR;
+    FixedOffset::east_opt(i32::try_from(rounded).ok()?)
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{TimeZone, Utc};
+
+    use super::{parse_format, parse_timezone, DateStyle, DisplayTimezone};
+
+    #[test]
+    fn test_timezones() {
+        let date = Utc.timestamp_millis_opt(1533000000123).unwrap();
+        let style = DateStyle {
+            timezone: DisplayTimezone::Backup,
+            ..Default::default()
+        };
+        let local = style.localize(date, "Jul 30, 2018 9:20:00\u{202F}PM");
+        assert_eq!(local.offset().local_minus_utc(), -4 * 3600);
+        assert_eq!(style.format_time(local), "9:20 PM");
+        assert_eq!(style.format_date(local), "Monday July 30 2018");
+        assert_eq!(style.format_day(local.date_naive()), "Monday July 30 2018");
+        let style = DateStyle {
+            timezone: parse_timezone("Europe/Paris").unwrap(),
+            hour24: true,
+            ..Default::default()
+        };
+        let local = style.localize(date, "");
+        assert_eq!(style.format_time(local), "03:20");
+        assert!(parse_timezone("Mars/Olympus_Mons").is_err());
+        assert!(parse_format("%Q").is_err());
+    }
+}
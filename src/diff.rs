@@ -0,0 +1,262 @@
+//! Compares two backups, to find the messages one of them is missing
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::model::{MmsMessage, SmsMessage, TextLog, TextMessage};
+
+/// A message from either half of a [TextLog]
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Message<'a> {
+    Sms(&'a SmsMessage),
+    Mms(&'a MmsMessage),
+}
+impl<'a> Message<'a> {
+    fn all(log: &'a TextLog) -> impl Iterator<Item = Message<'a>> {
+        log.sms_messages
+            .iter()
+            .map(Message::Sms)
+            .chain(log.mms_messages.iter().map(Message::Mms))
+    }
+    #[inline]
+    fn as_dyn(self) -> &'a dyn TextMessage {
+        match self {
+            Message::Sms(sms) => sms,
+            Message::Mms(mms) => mms,
+        }
+    }
+    /// Whether the messages are exactly the same, down to how their numbers are written
+    ///
+    /// Numbers are equal when they're the same once normalized, which isn't enough here.
+    fn identical(self, other: Message<'_>) -> bool {
+        self == other && self.raw_numbers() == other.raw_numbers()
+    }
+    fn raw_numbers(self) -> Vec<&'a str> {
+        match self {
+            Message::Sms(sms) => vec![sms.address.raw()],
+            Message::Mms(mms) => std::iter::once(&mms.address)
+                .chain(mms.addrs.iter().map(|addr| &addr.address))
+                .map(|number| number.raw())
+                .collect(),
+        }
+    }
+    /// The message as JSON, for finding which fields changed
+    fn fields(self) -> serde_json::Map<String, serde_json::Value> {
+        let value = match self {
+            Message::Sms(sms) => serde_json::to_value(sms),
+            Message::Mms(mms) => serde_json::to_value(mms),
+        };
+        match value {
+            Ok(serde_json::Value::Object(fields)) => fields,
+            _ => unreachable!("messages always serialize to objects"),
+        }
+    }
+}
+
+/// A message that's in both backups, but with some differences
+pub struct Changed<'a> {
+    /// The message in the new backup
+    pub new: &'a dyn TextMessage,
+    /// The fields that are different, in order of their names
+    pub fields: Vec<FieldChange>,
+}
+
+/// A field of a message that changed
+pub struct FieldChange {
+    /// The name of the field, as it's named in JSON dumps
+    pub name: String,
+    /// The old value (or null, if it wasn't there)
+    pub old: serde_json::Value,
+    pub new: serde_json::Value,
+}
+
+/// The differences in a single conversation
+pub struct ConversationDiff<'a> {
+    pub conversation_id: String,
+    /// The name of whoever the conversation is with, preferring the name in the new backup
+    pub contact: String,
+    /// The messages only in the new backup, sorted by date
+    pub added: Vec<&'a dyn TextMessage>,
+    /// The messages only in the old backup, sorted by date
+    pub removed: Vec<&'a dyn TextMessage>,
+    /// The messages in both backups that are different, sorted by date
+    pub changed: Vec<Changed<'a>>,
+    /// The number of messages that are exactly the same in both
+    pub unchanged: usize,
+}
+impl ConversationDiff<'_> {
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// Finds the messages that were added, removed or changed in every conversation
+///
+/// Messages are matched up by their [id](TextMessage::id), so changes to things
+/// like the contact name or readable date are changes rather than a new message.
+/// Conversations are in order of their ids, and include any without differences.
+pub fn diff<'a>(old: &'a TextLog, new: &'a TextLog) -> Vec<ConversationDiff<'a>> {
+    let mut old_by_id = HashMap::<String, Vec<Message<'a>>>::new();
+    for message in Message::all(old) {
+        old_by_id
+            .entry(message.as_dyn().id())
+            .or_default()
+            .push(message);
+    }
+    let mut conversations = BTreeMap::new();
+    for message in Message::all(new) {
+        // Identical messages have the same id, and get matched up in turn,
+        // preferring an exact match in case they differ in other ways
+        let matched = old_by_id
+            .get_mut(&message.as_dyn().id())
+            .filter(|candidates| !candidates.is_empty())
+            .map(|candidates| {
+                let index = candidates
+                    .iter()
+                    .position(|&candidate| candidate.identical(message))
+                    .unwrap_or(0);
+                candidates.remove(index)
+            });
+        let entry = entry(&mut conversations, message.as_dyn());
+        match matched {
+            None => entry.added.push(message.as_dyn()),
+            Some(old) if old.identical(message) => entry.unchanged += 1,
+            Some(old) => {
+                let (old_fields, new_fields) = (old.fields(), message.fields());
+                let mut names = old_fields
+                    .keys()
+                    .chain(new_fields.keys())
+                    .collect::<Vec<_>>();
+                names.sort();
+                names.dedup();
+                let fields = names
+                    .into_iter()
+                    .filter(|&name| old_fields.get(name) != new_fields.get(name))
+                    .map(|name| FieldChange {
+                        name: name.clone(),
+                        old: old_fields.get(name).cloned().unwrap_or_default(),
+                        new: new_fields.get(name).cloned().unwrap_or_default(),
+                    })
+                    .collect();
+                entry.changed.push(Changed {
+                    new: message.as_dyn(),
+                    fields,
+                });
+            }
+        }
+    }
+    for message in old_by_id.into_values().flatten() {
+        entry(&mut conversations, message.as_dyn())
+            .removed
+            .push(message.as_dyn());
+    }
+    let mut contacts = HashMap::new();
+    for conversation in old.conversations().iter().chain(&new.conversations()) {
+        contacts.insert(
+            conversation.id(),
+            crate::html::conversation_title(conversation),
+        );
+    }
+    let mut conversations = conversations.into_values().collect::<Vec<_>>();
+    for conversation in &mut conversations {
+        conversation.contact = contacts
+            .remove(&conversation.conversation_id)
+            .unwrap_or_default();
+        conversation.added.sort_by_key(|message| message.date());
+        conversation.removed.sort_by_key(|message| message.date());
+        conversation
+            .changed
+            .sort_by_key(|changed| changed.new.date());
+    }
+    conversations
+}
+
+fn entry<'a, 'b>(
+    conversations: &'b mut BTreeMap<String, ConversationDiff<'a>>,
+    message: &dyn TextMessage,
+) -> &'b mut ConversationDiff<'a> {
+    let conversation_id = message.conversation_id();
+    conversations
+        .entry(conversation_id.clone())
+        .or_insert_with(|| ConversationDiff {
+            conversation_id,
+            contact: String::new(),
+            added: Vec::new(),
+            removed: Vec::new(),
+            changed: Vec::new(),
+            unchanged: 0,
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::xml::testing::{log, received, sent};
+
+    const ALICE: &str = "+15551234567";
+
+    #[test]
+    fn test_diff() {
+        let old = log([
+            sent(ALICE, "Alice", "2018-07-31 01:20", "Hello"),
+            received(ALICE, "Alice", "2018-07-31 01:21", "Hi"),
+            received(ALICE, "Alice", "2018-07-31 01:21", "Hi"),
+            received("+15559876543", "Bob", "2018-07-31 01:22", "Lost"),
+        ]);
+        let new = log([
+            sent(ALICE, "Alice Smith", "2018-07-31 01:20", "Hello").replace(
+                r#"readable_date="""#,
+                r#"readable_date="Jul 30, 2018 9:20:00 PM""#,
+            ),
+            received(ALICE, "Alice", "2018-07-31 01:21", "Hi"),
+            sent(ALICE, "Alice Smith", "2018-07-31 01:23", "New"),
+            received("+15550000000", "(Unknown)", "2018-07-31 01:24", "Who?"),
+        ]);
+        let diffs = super::diff(&old, &new);
+        assert_eq!(diffs.len(), 3);
+        let alice = diffs
+            .iter()
+            .find(|diff| diff.contact == "Alice Smith")
+            .unwrap();
+        assert_eq!(alice.added.len(), 1);
+        assert_eq!(alice.added[0].body().text(), "New");
+        // One of the duplicates went missing
+        assert_eq!(alice.removed.len(), 1);
+        assert_eq!(alice.unchanged, 1);
+        assert_eq!(alice.changed.len(), 1);
+        let fields = &alice.changed[0].fields;
+        assert_eq!(fields.len(), 2);
+        assert_eq!(fields[0].name, "contact_name");
+        assert_eq!(fields[0].old, "Alice");
+        assert_eq!(fields[0].new, "Alice Smith");
+        assert_eq!(fields[1].name, "readable_date");
+        let bob = diffs.iter().find(|diff| diff.contact == "Bob").unwrap();
+        assert_eq!((bob.removed.len(), bob.added.len()), (1, 0));
+        let unknown = diffs
+            .iter()
+            .find(|diff| diff.contact == "+15550000000")
+            .unwrap();
+        assert_eq!(unknown.added.len(), 1);
+        assert!(super::diff(&old, &old).iter().all(|diff| diff.is_empty()));
+    }
+
+    #[test]
+    fn test_reformatted_address() {
+        let old = log([sent(ALICE, "Alice", "2018-07-31 01:20", "Hello")]);
+        let new = log([sent(
+            "+1 555-123-4567",
+            "Alice",
+            "2018-07-31 01:20",
+            "Hello",
+        )]);
+        let diffs = super::diff(&old, &new);
+        let alice = diffs.iter().find(|diff| diff.contact == "Alice").unwrap();
+        assert_eq!((alice.added.len(), alice.removed.len()), (0, 0));
+        assert_eq!(alice.unchanged, 0);
+        assert_eq!(alice.changed.len(), 1);
+        let fields = &alice.changed[0].fields;
+        assert_eq!(fields.len(), 1);
+        assert_eq!(fields[0].name, "address");
+        assert_eq!(fields[0].old, "+15551234567");
+        assert_eq!(fields[0].new, "+1 555-123-4567");
+    }
+}
@@ -0,0 +1,85 @@
+//! Exports messages into formats for other tools
+
+use std::io::Write;
+
+use chrono::SecondsFormat;
+use itertools::Itertools;
+use serde::Serialize;
+
+use crate::model::TextMessage;
+
+/// A single row of the CSV export
+#[derive(Serialize)]
+struct CsvRow<'a> {
+    date: String,
+    readable_date: &'a str,
+    direction: &'static str,
+    address: &'a str,
+    contact_name: &'a str,
+    body: &'a str,
+    attachment_count: usize,
+    /// The content types of the attachments, separated by `;`
+    attachment_types: String,
+}
+
+/// Writes the messages as CSV, with one row per message
+pub fn write_csv<'a, W: Write>(
+    messages: impl IntoIterator<Item = &'a dyn TextMessage>,
+    out: W,
+) -> csv::Result<()> {
+    let mut writer = csv::Writer::from_writer(out);
+    for message in messages {
+        let body = message.body();
+        let attachments = body.attachments().collect::<Vec<_>>();
+        writer.serialize(CsvRow {
+            date: message.date().to_rfc3339_opts(SecondsFormat::Secs, true),
+            readable_date: message.readable_date(),
+            direction: message.kind().name(),
+            address: message.address().raw(),
+            contact_name: message.contact_name(),
+            body: &body.text(),
+            attachment_count: attachments.len(),
+            attachment_types: attachments.iter().map(|part| &*part.content_type).join(";"),
+        })?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::xml::testing::{data_part, log, received_mms, sent, text_part};
+
+    #[test]
+    fn test_csv() {
+        let smil = r#"<part seq="0" ct="application/smil" cl="smil.xml" text="&lt;smil /&gt;" />"#;
+        let parts = [
+            smil.to_owned(),
+            data_part("image/png", "a.png", "iVBORw0KGgo="),
+            text_part("Look"),
+        ];
+        let log = log([
+            sent(
+                "+15551234567",
+                "Alice",
+                "2018-07-31 01:20",
+                "Hello, \"Alice\"\nBye",
+            )
+            .replace(
+                r#"readable_date="""#,
+                r#"readable_date="Jul 30, 2018 9:20:00 PM""#,
+            ),
+            received_mms("+15551234567", "Alice", "2018-07-31 01:21", &parts, &[]),
+        ]);
+        let mut out = Vec::new();
+        super::write_csv(log.iter(), &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "date,readable_date,direction,address,contact_name,body,attachment_count,attachment_types
+2018-07-31T01:20:00Z,\"Jul 30, 2018 9:20:00 PM\",sent,+15551234567,Alice,\"Hello, \"\"Alice\"\"
+Bye\",0,
+2018-07-31T01:21:00Z,,received,+15551234567,Alice,Look,1,image/png
+"
+        );
+    }
+}
@@ -0,0 +1,219 @@
+//! Restricts which messages a command works with
+
+use chrono::{DateTime, Days, NaiveDate, NaiveDateTime, Utc};
+
+use crate::dates::DateStyle;
+use crate::model::{CallLog, TextLog};
+
+/// Restricts messages (and calls) to a range of dates
+///
+/// Dates are either a day (like `2020-01-31`) or a time (like `2020-01-31T09:30`),
+/// which are in the display timezone unless they include an offset (like `2020-01-31T09:30+01:00`).
+#[derive(clap::Args, Clone, Debug, Default)]
+pub struct DateRange {
+    /// Only include messages from this date onwards
+    #[arg(long, global = true, value_parser = parse_since)]
+    pub since: Option<DateBound>,
+    /// Only include messages before this date (or up to the end of it, if it's just a day)
+    #[arg(long, global = true, value_parser = parse_until)]
+    pub until: Option<DateBound>,
+}
+
+/// One end of a [DateRange]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DateBound {
+    /// An exact moment, from a date with an offset
+    Exact(DateTime<Utc>),
+    /// A date and time in the display timezone
+    ///
+    /// These are compared with the date of each message as it's displayed,
+    /// so times that are skipped or repeated by daylight saving need no special handling
+    /// (and the `backup` timezone works too).
+    Local(NaiveDateTime),
+}
+impl DateBound {
+    /// Whether this bound comes after the date of a message
+    fn is_after(self, date: DateTime<Utc>, readable_date: &str, style: &DateStyle) -> bool {
+        match self {
+            DateBound::Exact(bound) => date < bound,
+            DateBound::Local(bound) => style.localize(date, readable_date).naive_local() < bound,
+        }
+    }
+}
+
+impl DateRange {
+    /// Whether the date is in the range, when displayed in the given style
+    ///
+    /// The `readable_date` is only needed for the `backup` timezone.
+    pub fn contains(&self, date: DateTime<Utc>, readable_date: &str, style: &DateStyle) -> bool {
+        self.since
+            .is_none_or(|since| !since.is_after(date, readable_date, style))
+            && self
+                .until
+                .is_none_or(|until| until.is_after(date, readable_date, style))
+    }
+    #[inline]
+    fn is_unbounded(&self) -> bool {
+        self.since.is_none() && self.until.is_none()
+    }
+    /// Drops every message outside the range
+    pub fn filter_log(&self, log: &mut TextLog, style: &DateStyle) {
+        if self.is_unbounded() {
+            return;
+        }
+        let before = log.sms_messages.len() + log.mms_messages.len();
+        log.sms_messages
+            .retain(|sms| self.contains(sms.date, &sms.readable_date, style));
+        log.mms_messages
+            .retain(|mms| self.contains(mms.date, &mms.readable_date, style));
+        let after = log.sms_messages.len() + log.mms_messages.len();
+        log::info!("Kept {} of {} messages in the date range", after, before);
+    }
+    /// Drops every call outside the range
+    pub fn filter_calls(&self, log: &mut CallLog, style: &DateStyle) {
+        if self.is_unbounded() {
+            return;
+        }
+        let before = log.calls.len();
+        log.calls
+            .retain(|call| self.contains(call.date, &call.readable_date, style));
+        log::info!(
+            "Kept {} of {} calls in the date range",
+            log.calls.len(),
+            before
+        );
+    }
+}
+
+fn parse_since(text: &str) -> Result<DateBound, String> {
+    parse_date(text, false)
+}
+/// Parses the end of a range, which includes the whole day if only a day is given
+fn parse_until(text: &str) -> Result<DateBound, String> {
+    parse_date(text, true)
+}
+fn parse_date(text: &str, end_of_day: bool) -> Result<DateBound, String> {
+    if let Ok(date) = DateTime::parse_from_rfc3339(text) {
+        return Ok(DateBound::Exact(date.with_timezone(&Utc)));
+    }
+    for format in [
+        "%Y-%m-%dT%H:%M%:z",
+        "%Y-%m-%d %H:%M:%S%:z",
+        "%Y-%m-%d %H:%M%:z",
+    ] {
+        if let Ok(date) = DateTime::parse_from_str(text, format) {
+            return Ok(DateBound::Exact(date.with_timezone(&Utc)));
+        }
+    }
+    for format in [
+        "%Y-%m-%dT%H:%M:%S",
+        "%Y-%m-%dT%H:%M",
+        "%Y-%m-%d %H:%M:%S",
+        "%Y-%m-%d %H:%M",
+    ] {
+        if let Ok(date) = NaiveDateTime::parse_from_str(text, format) {
+            return Ok(DateBound::Local(date));
+        }
+    }
+    match NaiveDate::parse_from_str(text, "%Y-%m-%d") {
+        Ok(date) => {
+            let date = if end_of_day {
+                date.checked_add_days(Days::new(1))
+                    .ok_or("Date out of range")?
+            } else {
+                date
+            };
+            Ok(DateBound::Local(date.and_hms_opt(0, 0, 0).unwrap()))
+        }
+        Err(_) => Err(format!(
+            "Invalid date {:?}, expected something like 2020-01-31 or 2020-01-31T09:30",
+            text
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{NaiveDate, TimeZone, Utc};
+
+    use super::{parse_since, parse_until, DateBound, DateRange};
+    use crate::dates::{DateStyle, DisplayTimezone};
+
+    #[test]
+    fn test_date_range() {
+        let range = DateRange {
+            since: Some(parse_since("2018-07-30").unwrap()),
+            until: Some(parse_until("2018-07-31").unwrap()),
+        };
+        let utc = DateStyle {
+            timezone: DisplayTimezone::Named(chrono_tz::UTC),
+            ..Default::default()
+        };
+        let contains = |range: &DateRange, date, style| range.contains(date, "", style);
+        assert!(contains(
+            &range,
+            Utc.with_ymd_and_hms(2018, 7, 30, 0, 0, 0).unwrap(),
+            &utc
+        ));
+        assert!(contains(
+            &range,
+            Utc.with_ymd_and_hms(2018, 7, 31, 23, 59, 59).unwrap(),
+            &utc
+        ));
+        assert!(!contains(
+            &range,
+            Utc.with_ymd_and_hms(2018, 8, 1, 0, 0, 0).unwrap(),
+            &utc
+        ));
+        assert!(!contains(
+            &range,
+            Utc.with_ymd_and_hms(2018, 7, 29, 23, 59, 59).unwrap(),
+            &utc
+        ));
+        assert_eq!(
+            parse_since("2018-07-31T01:20:00+02:00").unwrap(),
+            DateBound::Exact(Utc.with_ymd_and_hms(2018, 7, 30, 23, 20, 0).unwrap())
+        );
+        assert_eq!(
+            parse_since("2018-07-31T01:20+02:00").unwrap(),
+            DateBound::Exact(Utc.with_ymd_and_hms(2018, 7, 30, 23, 20, 0).unwrap())
+        );
+        assert_eq!(
+            parse_until("2018-07-31T01:20").unwrap(),
+            DateBound::Local(
+                NaiveDate::from_ymd_opt(2018, 7, 31)
+                    .unwrap()
+                    .and_hms_opt(1, 20, 0)
+                    .unwrap()
+            )
+        );
+        assert!(parse_since("yesterday").is_err());
+
+        // Days are in the display timezone, so 01:20 UTC is still the 30th in New York
+        let new_york = DateStyle {
+            timezone: DisplayTimezone::Named(chrono_tz::America::New_York),
+            ..Default::default()
+        };
+        let date = Utc.with_ymd_and_hms(2018, 7, 31, 1, 20, 0).unwrap();
+        let range = DateRange {
+            since: Some(parse_since("2018-07-31").unwrap()),
+            until: None,
+        };
+        assert!(contains(&range, date, &utc));
+        assert!(!contains(&range, date, &new_york));
+        let range = DateRange {
+            since: None,
+            until: Some(parse_until("2018-07-30").unwrap()),
+        };
+        assert!(contains(&range, date, &new_york));
+        // Including the hour that's repeated when the clocks go back
+        let range = DateRange {
+            since: Some(parse_since("2018-11-04T01:00").unwrap()),
+            until: Some(parse_until("2018-11-04T02:00").unwrap()),
+        };
+        for hour in 5..7 {
+            let date = Utc.with_ymd_and_hms(2018, 11, 4, hour, 30, 0).unwrap();
+            assert!(contains(&range, date, &new_york), "{}", date);
+        }
+    }
+}
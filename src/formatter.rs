@@ -11,6 +11,8 @@ pub fn to_string_escaped<V: Serialize>(v: &V) -> String {
 }
 
 struct EscapingFormatter<F>(pub F);
+// The signatures are copied from the trait, bounds and all
+#[allow(clippy::multiple_bound_locations)]
 impl<F: Formatter> Formatter for EscapingFormatter<F> {
     fn write_string_fragment<W: ?Sized>(&mut self, writer: &mut W, fragment: &str) -> io::Result<()>
     where
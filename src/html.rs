@@ -1,19 +1,92 @@
 //! Generates nicely formatted HTML from a text message
 
 use base64::{engine::general_purpose::STANDARD as BASE64_ENGINE, Engine};
+use chrono::{DateTime, Utc};
 use itertools::Itertools;
 use maud::{html, Markup};
 
-use super::model::{BodyKind, MessageKind, MmsMessagePart, TextLog, TextMessage};
+use super::dates::DateStyle;
+use super::model::{
+    BodyKind, CallKind, CallRecord, Conversation, MessageKind, MmsMessagePart, TextMessage,
+    TimelineItem, UNKNOWN_CONTACT_NAME,
+};
 
 const CSS: &str = include_str!("sms.css");
+/// The subset of bootstrap we need, for when we're not using the CDN
+const BOOTSTRAP_CSS: &str = include_str!("bootstrap.css");
 
-pub fn render_log(log: &TextLog, contact: &str) -> Markup {
-    let mut messages = log
-        .iter()
-        .filter(|message| message.contact_name() == contact)
-        .collect_vec();
-    messages.sort_by_key(|message| message.date());
+/// Options controlling how the HTML is rendered
+#[derive(Default)]
+pub struct RenderOptions {
+    /// Link to bootstrap on a CDN instead of inlining it
+    ///
+    /// This makes the output slightly smaller,
+    /// at the cost of needing network access to view it.
+    pub use_cdn: bool,
+    /// How to include attachments
+    pub attachments: AttachmentMode,
+    /// How dates are displayed
+    pub dates: DateStyle,
+    /// Put charts of the activity in a conversation at the top of its page
+    pub summary: bool,
+}
+
+/// How attachments are included in the page
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum AttachmentMode {
+    /// Embed the data in the page itself, as `data:` URLs
+    #[default]
+    Inline,
+    /// Link to files alongside the page
+    ///
+    /// The files are expected to be named by [MmsMessagePart::attachment_file_name],
+    /// and it's up to the caller to write them.
+    Linked,
+}
+
+/// The name of whoever the conversation is with
+pub fn conversation_title(conversation: &Conversation<'_>) -> String {
+    match conversation.contact_name() {
+        UNKNOWN_CONTACT_NAME => conversation.participants.iter().join(", "),
+        name => name.to_owned(),
+    }
+}
+
+/// Renders a conversation, along with any `calls` with the same contact
+///
+/// The `header` (like a link back to an index) goes above the title.
+pub fn render_conversation(
+    conversation: &Conversation<'_>,
+    calls: &[CallRecord],
+    header: Option<Markup>,
+    options: &RenderOptions,
+) -> Markup {
+    let contact = conversation_title(conversation);
+    render_page(
+        &contact,
+        options,
+        html! {
+            div class="container" {
+                @if let Some(header) = header {
+                    (header)
+                }
+                h3 class="text-center" { "Messages with " (contact) }
+            }
+            @if options.summary {
+                (crate::charts::render_summary(conversation, &options.dates))
+            }
+            @for item in conversation.timeline(calls) {
+                @match item {
+                    TimelineItem::Message(message) => (render_message(message, options)),
+                    TimelineItem::Call(call) => (render_call(call, false, options)),
+                }
+            }
+        },
+    )
+}
+
+/// Wraps the body in a complete page, including all the styles
+pub fn render_page(title: &str, options: &RenderOptions, body: Markup) -> Markup {
     html! {
         (::maud::DOCTYPE)
         html {
@@ -23,87 +96,151 @@ pub fn render_log(log: &TextLog, contact: &str) -> Markup {
              * Thankfully, I didn't have to mess very much with the CSS......
              */
             head {
-                link rel="stylesheet"
-                    href="https://stackpath.bootstrapcdn.com/bootstrap/4.1.3/css/bootstrap.min.css"
-                    integrity="sha384-MCw98/SFnGE8fJT3GXwEOngsV7Zt27NXFoaoApmYm81iuXoPkFOJwJ8ERdknLPMO"
-                    crossorigin="anonymous";
+                @if options.use_cdn {
+                    link rel="stylesheet"
+                        href="https://stackpath.bootstrapcdn.com/bootstrap/4.1.3/css/bootstrap.min.css"
+                        integrity="sha384-MCw98/SFnGE8fJT3GXwEOngsV7Zt27NXFoaoApmYm81iuXoPkFOJwJ8ERdknLPMO"
+                        crossorigin="anonymous";
+                } @else {
+                    style { (BOOTSTRAP_CSS) }
+                }
                 style { (CSS) }
                 meta charset="UTF-8";
+                title { (title) }
             }
-            body {
-                div class="container" {
-                    h3 class="text-center" { "Messages with " (contact) }
-
-                }
-                @for message in messages {
-                    (render_message(message))
-                }
-            }
+            body { (body) }
         }
     }
 }
 
-pub fn render_message(message: &dyn TextMessage) -> Markup {
-    match message.kind() {
-        MessageKind::Sent => {
-            html!(div class="outgoing_msg" {
-                div class="sent_msg" {
-                    ({ render_body(message) })
-                    span class="time_date" { ({ render_date(message) }) }
-                }
-            })
-        }
+pub fn render_message(message: &dyn TextMessage, options: &RenderOptions) -> Markup {
+    let (class, status) = match message.kind() {
         MessageKind::Received { .. } => {
-            html!(div class="incoming_msg" {
+            return html!(div class="incoming_msg" id=(message_anchor(message)) {
                 // TODO: incoming_msg_img
                 div class="received_msg" {
                     div class="received_withd_msg" {
-                        ({ render_body(message) })
-                        span class="time_date" { ({ render_date(message) }) }
+                        @if let (Some(sender), true) = (message.sender(), message.participants().len() > 1) {
+                            span class="msg_sender" { (sender) }
+                        }
+                        ({ render_body(message, options) })
+                        span class="time_date" { ({ render_date(message.date(), message.readable_date(), &options.dates) }) }
                     }
                 }
-            })
+            });
         }
+        MessageKind::Sent => ("sent_msg", None),
+        MessageKind::Draft => ("sent_msg draft_msg", Some("Draft")),
+        MessageKind::Outbox => ("sent_msg outbox_msg", Some("Sending")),
+        MessageKind::Failed => ("sent_msg failed_msg", Some("Failed to send")),
+        MessageKind::Queued => ("sent_msg queued_msg", Some("Queued")),
+    };
+    html!(div class="outgoing_msg" id=(message_anchor(message)) {
+        div class=(class) {
+            ({ render_body(message, options) })
+            span class="time_date" {
+                @if let Some(status) = status {
+                    b class="msg_status" { (status) } "    |    "
+                }
+                ({ render_date(message.date(), message.readable_date(), &options.dates) })
+            }
+        }
+    })
+}
+/// The id of a message's element, so it can be linked to as `page.html#msg-<id>`
+pub fn message_anchor(message: &dyn TextMessage) -> String {
+    format!("msg-{}", message.id())
+}
+/// Renders a page listing calls, in the order given
+pub fn render_call_log(title: &str, calls: &[&CallRecord], options: &RenderOptions) -> Markup {
+    render_page(
+        title,
+        options,
+        html! {
+            div class="container" {
+                h3 class="text-center" { (title) }
+            }
+            @for &call in calls {
+                (render_call(call, true, options))
+            }
+        },
+    )
+}
+/// Renders a single call, optionally including who it was with
+pub fn render_call(call: &CallRecord, show_contact: bool, options: &RenderOptions) -> Markup {
+    let class = match call.kind {
+        CallKind::Missed | CallKind::Rejected | CallKind::Blocked => "call_entry call_missed",
+        _ => "call_entry",
+    };
+    html!(div class=(class) {
+        p {
+            b { (call.kind.description()) }
+            @if show_contact {
+                " - " (call_contact(call))
+            }
+            @if call.duration > 0 {
+                " (" (crate::utils::format_duration(call.duration)) ")"
+            }
+        }
+        span class="time_date" { ({ render_date(call.date, &call.readable_date, &options.dates) }) }
+    })
+}
+/// The name of whoever was on the other end of the call
+fn call_contact(call: &CallRecord) -> String {
+    match &*call.contact_name {
+        _ if call.number.raw().is_empty() => "Private number".into(),
+        UNKNOWN_CONTACT_NAME | "" => call.number.to_string(),
+        name => format!("{} ({})", name, call.number),
     }
 }
-pub fn render_date(message: &dyn TextMessage) -> Markup {
-    let date_format = message.date().format("%A %B %e %Y").to_string();
-    let time_format = message.date().time().format("%-I:%M %p").to_string();
-    html!((time_format) "    |    " (date_format))
+pub fn render_date(date: DateTime<Utc>, readable_date: &str, style: &DateStyle) -> Markup {
+    let date = style.localize(date, readable_date);
+    html!((style.format_time(date)) "    |    " (style.format_date(date)))
 }
-pub fn render_body(message: &dyn TextMessage) -> Markup {
+pub fn render_body(message: &dyn TextMessage, options: &RenderOptions) -> Markup {
     match message.body() {
         BodyKind::Sms(text) => html! { p { (text) } },
         BodyKind::Mms { parts } => html! {
             @for part in parts {
-                (render_part(part))
+                (render_part(part, options))
             }
         },
     }
 }
-pub fn render_part(message: &MmsMessagePart) -> Markup {
+pub fn render_part(message: &MmsMessagePart, options: &RenderOptions) -> Markup {
     let text = message.text.as_ref();
     let data = message.data.as_ref();
-    let encode_base64_data = || {
-        format!(
-            "data:{};base64,{}",
-            &message.content_type,
-            BASE64_ENGINE.encode(&**data.unwrap())
-        )
+    // Parts can be missing their data, if the phone had already deleted it
+    let source = || match options.attachments {
+        AttachmentMode::Inline => data.map(|data| {
+            format!(
+                "data:{};base64,{}",
+                &message.content_type,
+                BASE64_ENGINE.encode(&**data)
+            )
+        }),
+        AttachmentMode::Linked => message.attachment_file_name(),
     };
+    let missing = || html!(p { b { "Missing attachment" } " (" (message.content_type) ")" });
     match &*message.content_type {
         "application/smil" => html!(),
-        "text/plain" => html!(p { (text.unwrap()) }),
-        "image/jpeg" | "image/png" | "image/gif" => {
-            let data = encode_base64_data();
-            html!(img src=(data) {})
+        "text/plain" => html!(p { (text.map_or("", String::as_str)) }),
+        "image/jpeg" | "image/png" | "image/gif" => match source() {
+            Some(data) => html!(img src=(data) {}),
+            None => missing(),
+        },
+        "audio/amr" if options.attachments == AttachmentMode::Linked && data.is_some() => {
+            html!(p { a href=(source().unwrap()) { "Download audio" } })
         }
         "audio/amr" => html!(p { b { "Unsupported audio" } }),
-        "video/mp4" | "video/3gpp" => {
-            let data = encode_base64_data();
-            html!(video controls {
+        "video/mp4" | "video/3gpp" => match source() {
+            Some(data) => html!(video controls {
                 source src=(data) type=(message.content_type);
-            })
+            }),
+            None => missing(),
+        },
+        _ if options.attachments == AttachmentMode::Linked && data.is_some() => {
+            html!(p { a href=(source().unwrap()) { "Download attachment" } " (" (message.content_type) ")" })
         }
         _ => {
             log::warn!(
@@ -114,3 +251,37 @@ pub fn render_part(message: &MmsMessagePart) -> Markup {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{render_part, AttachmentMode, RenderOptions};
+    use crate::model::MmsMessagePart;
+    use crate::xml::testing::{log, received_mms};
+
+    #[test]
+    fn test_missing_attachments() {
+        // Parts without any data
+        let parts = [
+            r#"<part seq="0" ct="image/jpeg" cl="IMG_1.jpg" text="null" />"#.to_owned(),
+            r#"<part seq="0" ct="video/mp4" cl="VID_1.mp4" text="null" />"#.to_owned(),
+        ];
+        let log = log([received_mms(
+            "+15551234567",
+            "Alice",
+            "2018-07-31 01:20",
+            &parts,
+            &[],
+        )]);
+        let parts: &[MmsMessagePart] = &log.mms_messages[0].parts;
+        for attachments in [AttachmentMode::Inline, AttachmentMode::Linked] {
+            let options = RenderOptions {
+                attachments,
+                ..Default::default()
+            };
+            for part in parts {
+                let html = render_part(part, &options).into_string();
+                assert!(html.contains("Missing attachment"), "{}", html);
+            }
+        }
+    }
+}
@@ -1,21 +1,42 @@
 #![warn(rust_2021_compatibility, rust_2018_compatibility, rust_2018_idioms)]
+use std::borrow::Cow;
 use std::collections::{HashMap, HashSet};
 use std::ffi::OsStr;
 use std::fs;
-use std::io::{BufReader, Read};
+use std::io::{BufReader, BufWriter};
 use std::path::{Path, PathBuf};
 use std::time::Instant;
 
 use itertools::Itertools;
 
+mod anonymize;
+mod attachments;
+mod blobs;
+mod charts;
+mod dates;
+mod diff;
+mod export;
+mod filter;
 mod formatter;
 mod html;
+mod merge;
 mod model;
+mod phone;
 mod sanitize;
+mod search;
+mod select;
+mod site;
+mod sqlite;
+mod stats;
 mod utils;
 mod xml;
 
-use self::model::PhoneNumber;
+use self::dates::DateStyle;
+use self::filter::DateRange;
+use self::html::RenderOptions;
+use self::merge::IdentityField;
+use self::model::{PhoneNumber, TextMessage, UNKNOWN_CONTACT_NAME};
+use self::select::ConversationArgs;
 
 /// A set of utilities for processing SMS backups
 ///
@@ -27,6 +48,22 @@ struct App {
     /// Gives verbose error and status information
     #[arg(short, long)]
     verbose: bool,
+    /// Skip malformed records in XML backups instead of aborting
+    #[arg(long)]
+    lenient: bool,
+    /// The country code assumed for phone numbers without one (for example 1 or 44)
+    ///
+    /// This is used to recognize different ways of writing the same number.
+    /// If it's not given, it's taken from the dump being read (which records it),
+    /// or else guessed from the most common country code among the numbers that have one.
+    /// Conversation and message ids depend on it too, so use the same one each time
+    /// (it's recorded in JSON dumps and SQLite exports, to warn when it changes).
+    #[arg(long)]
+    country_code: Option<String>,
+    #[command(flatten)]
+    dates: DateRange,
+    #[command(flatten)]
+    date_style: DateStyle,
     #[command(subcommand)]
     command: Command,
 }
@@ -34,12 +71,38 @@ struct App {
 #[derive(clap::Subcommand)]
 enum Command {
     /// Renders a HTML file of all texts with a particular contact
+    #[command(mut_group("ConversationArgs", |group| group.required(true)))]
     RenderHtml {
         /// The input XML file
         input_file: PathBuf,
-        /// The contact whose texts to print
+        /// The conversation to print
+        #[command(flatten)]
+        conversation: ConversationArgs,
+        /// Load bootstrap from a CDN instead of embedding it in the page
+        ///
+        /// The page will look broken without network access.
+        #[arg(long)]
+        cdn: bool,
+        /// A call log backup from the same phone, whose calls are shown alongside the texts
+        #[arg(long)]
+        calls: Option<PathBuf>,
+        /// Start the page with a heatmap of the conversation and a chart of each month
+        #[arg(long)]
+        summary: bool,
+    },
+    /// Renders every conversation into a directory of HTML pages, with an index
+    RenderSite {
+        /// The input file
+        input_file: PathBuf,
+        /// The directory to write the pages to
         #[arg(long, required = true)]
-        contact: String,
+        output: PathBuf,
+        /// Load bootstrap from a CDN instead of embedding it in every page
+        #[arg(long)]
+        cdn: bool,
+        /// Start each conversation with a heatmap and a chart of each month
+        #[arg(long)]
+        summary: bool,
     },
     /// Lists the names of all contexts ever texted
     ListContacts(ListContacts),
@@ -50,6 +113,108 @@ enum Command {
         /// Output JSON file
         #[arg(long, required = true)]
         output: PathBuf,
+        /// Store attachments in a `blobs` directory next to the output, instead of inline
+        ///
+        /// This keeps the JSON small, and identical attachments are only stored once.
+        #[arg(long)]
+        blobs: bool,
+    },
+    /// Writes the input file back out as an XML backup that can be restored
+    ToXml {
+        /// The input file to read from
+        input_file: PathBuf,
+        /// Output XML file
+        #[arg(long, required = true)]
+        output: PathBuf,
+    },
+    /// Merges several backups into one, dropping duplicate messages
+    Merge(Merge),
+    /// Writes every MMS attachment to a directory, along with a manifest
+    ExtractAttachments {
+        /// The input file to read from
+        input_file: PathBuf,
+        /// The directory to write the attachments to
+        #[arg(long, required = true)]
+        output: PathBuf,
+    },
+    /// Exports messages as CSV, with one row per message
+    ExportCsv(ExportCsv),
+    /// Exports the input file to a SQLite database, for querying with SQL
+    ///
+    /// The database can be used as the input to any other command.
+    ExportSqlite {
+        /// The input file to read from
+        input_file: PathBuf,
+        /// Output database file
+        #[arg(long, required = true)]
+        output: PathBuf,
+    },
+    /// Searches the text of every message
+    Search(Search),
+    /// Summarizes who was texted, when, and how quickly they replied
+    Stats(Stats),
+    /// Compares two backups, listing the messages added, removed or changed in each conversation
+    ///
+    /// Messages are matched up by their contents, so a change to something like the
+    /// contact name shows up as a change (rather than a message removed and another added).
+    Diff(Diff),
+    /// Replaces numbers, names, text and attachments, so a backup can be shared
+    ///
+    /// The output still has the same shape (the same conversations, lengths of text,
+    /// types of attachments and so on), so it can be used to reproduce bugs.
+    Anonymize {
+        /// The input file to read from
+        input_file: PathBuf,
+        /// Output file (XML, JSON or SQLite)
+        #[arg(long, required = true)]
+        output: PathBuf,
+        /// A secret that the pseudonyms are derived from
+        ///
+        /// The same salt always gives the same pseudonyms,
+        /// so keep it secret (or the pseudonyms of known numbers can be worked out).
+        #[arg(long, required = true)]
+        salt: String,
+    },
+    /// Works with call log backups (`calls-*.xml`)
+    Calls {
+        #[command(subcommand)]
+        command: CallsCommand,
+    },
+}
+
+#[derive(clap::Subcommand)]
+enum CallsCommand {
+    /// Renders a HTML file of all calls, or just those with a particular number
+    RenderHtml {
+        /// The input XML file
+        input_file: PathBuf,
+        /// Only include calls with this number
+        #[arg(long)]
+        number: Option<String>,
+        /// Load bootstrap from a CDN instead of embedding it in the page
+        #[arg(long)]
+        cdn: bool,
+    },
+    /// Lists every number ever called, with the number of calls
+    ListContacts {
+        /// The input XML file to read from
+        input_file: PathBuf,
+    },
+    /// Dumps a json formatted version of the input file
+    DumpJson {
+        /// The input XML file to read from
+        input_file: PathBuf,
+        /// Output JSON file
+        #[arg(long, required = true)]
+        output: PathBuf,
+    },
+    /// Writes the input file back out as an XML backup that can be restored
+    ToXml {
+        /// The input file to read from
+        input_file: PathBuf,
+        /// Output XML file
+        #[arg(long, required = true)]
+        output: PathBuf,
     },
 }
 
@@ -58,32 +223,460 @@ fn main() -> anyhow::Result<()> {
         .format_timestamp(None)
         .init();
     let app = <App as clap::Parser>::parse();
+    if let Some(country_code) = &app.country_code {
+        crate::phone::set_default_country_code(country_code)?;
+    }
     let options = CommonOptions {
         verbose: app.verbose,
+        lenient: app.lenient,
+        dates: app.dates,
+        date_style: app.date_style,
     };
     match app.command {
         Command::RenderHtml {
             input_file,
-            contact,
+            conversation,
+            cdn,
+            calls,
+            summary,
+        } => {
+            let log = options.parse_log(&input_file)?;
+            let conversation = conversation.select(&log)?;
+            let calls = match calls {
+                Some(path) => options.parse_call_log(&path)?,
+                None => Default::default(),
+            };
+            let render_options = RenderOptions {
+                use_cdn: cdn,
+                dates: options.date_style.clone(),
+                summary,
+                ..Default::default()
+            };
+            println!(
+                "{}",
+                crate::html::render_conversation(
+                    &conversation,
+                    &calls.calls,
+                    None,
+                    &render_options
+                )
+                .0
+            );
+        }
+        Command::RenderSite {
+            input_file,
+            output,
+            cdn,
+            summary,
         } => {
             let log = options.parse_log(&input_file)?;
-            println!("{}", crate::html::render_log(&log, &contact).0);
+            let render_options = RenderOptions {
+                use_cdn: cdn,
+                dates: options.date_style.clone(),
+                summary,
+                ..Default::default()
+            };
+            let count = crate::site::render_site(&log, &output, render_options)?;
+            log::info!("Wrote {} conversations to {}", count, output.display());
         }
         Command::ListContacts(args) => list_contacts(&options, &args)?,
-        Command::DumpJson { input_file, output } => {
+        Command::DumpJson {
+            input_file,
+            output,
+            blobs,
+        } => {
+            let mut log = options.parse_log(&input_file)?;
+            if blobs {
+                let dir = output.parent().unwrap_or(Path::new(""));
+                let written = crate::blobs::store(&mut log, dir)?;
+                log::info!("Wrote {} new blobs", written);
+            }
+            fs::write(output, crate::formatter::to_string_escaped(&log))?;
+        }
+        Command::ToXml { input_file, output } => {
+            let log = options.parse_log(&input_file)?;
+            let file = BufWriter::new(fs::File::create(output)?);
+            crate::xml::write_log(&log, file)?;
+        }
+        Command::Merge(args) => merge(&options, args)?,
+        Command::ExtractAttachments { input_file, output } => {
+            let log = options.parse_log(&input_file)?;
+            let extracted = crate::attachments::extract(&log, &output)?;
+            println!(
+                "Extracted {} attachments into {} files ({} duplicates)",
+                extracted.manifest.len(),
+                extracted.files,
+                extracted.manifest.len() - extracted.files
+            );
+        }
+        Command::Calls { command } => calls(&options, command)?,
+        Command::ExportCsv(args) => export_csv(&options, &args)?,
+        Command::Search(args) => search(&options, &args)?,
+        Command::Stats(args) => stats(&options, &args)?,
+        Command::Diff(args) => diff(&options, &args)?,
+        Command::Anonymize {
+            input_file,
+            output,
+            salt,
+        } => {
+            let mut log = options.parse_log(&input_file)?;
+            crate::anonymize::Anonymizer::new(&salt).anonymize_log(&mut log);
+            options.write_log(&output, &log)?;
+        }
+        Command::ExportSqlite { input_file, output } => {
             let log = options.parse_log(&input_file)?;
+            crate::sqlite::write_log(&log, &output)?;
+        }
+    }
+    Ok(())
+}
+#[derive(clap::Args)]
+struct Search {
+    /// The input file to read from
+    input_file: PathBuf,
+    /// The text to search for (case-insensitive)
+    query: String,
+    /// Treat the query as a regular expression
+    #[arg(long)]
+    regex: bool,
+    /// Match the case of a regular expression exactly
+    #[arg(long, requires = "regex")]
+    case_sensitive: bool,
+    /// The number of messages to show before and after each match
+    #[arg(short = 'C', long, default_value_t = 0)]
+    context: usize,
+    /// Only search a single conversation
+    #[command(flatten)]
+    conversation: Option<ConversationArgs>,
+}
+fn search(options: &CommonOptions, args: &Search) -> anyhow::Result<()> {
+    let matcher = if args.regex {
+        crate::search::Matcher::regex(&args.query, args.case_sensitive)?
+    } else {
+        crate::search::Matcher::substring(&args.query)
+    };
+    let log = options.parse_log(&args.input_file)?;
+    let conversations = match &args.conversation {
+        Some(conversation) => vec![conversation.select(&log)?],
+        None => log.conversations(),
+    };
+    let results = crate::search::search(&conversations, &matcher, args.context);
+    for result in &results {
+        let conversation = result.conversation;
+        println!(
+            "{}",
+            bold_underline(format!(
+                "{} ({})",
+                crate::html::conversation_title(conversation),
+                conversation.id()
+            ))
+        );
+        for (i, range) in result.ranges.iter().enumerate() {
+            if i > 0 {
+                println!("  --");
+            }
+            for index in range.clone() {
+                let message = conversation.messages[index];
+                let marker = if result.hits.contains(&index) {
+                    '>'
+                } else {
+                    ' '
+                };
+                options.print_message(marker, message);
+            }
+        }
+    }
+    let hits = results
+        .iter()
+        .map(|result| result.hits.len())
+        .sum::<usize>();
+    log::info!("Found {} messages in {} conversations", hits, results.len());
+    Ok(())
+}
+#[derive(clap::Args)]
+struct Diff {
+    /// The older backup
+    old_file: PathBuf,
+    /// The newer backup
+    new_file: PathBuf,
+    /// List the messages themselves, not just how many there are
+    #[arg(long)]
+    content: bool,
+}
+fn diff(options: &CommonOptions, args: &Diff) -> anyhow::Result<()> {
+    let old = options.parse_log(&args.old_file)?;
+    let new = options.parse_log(&args.new_file)?;
+    let diffs = crate::diff::diff(&old, &new);
+    let (mut added, mut removed, mut changed, mut unchanged) = (0, 0, 0, 0);
+    for diff in &diffs {
+        added += diff.added.len();
+        removed += diff.removed.len();
+        changed += diff.changed.len();
+        unchanged += diff.unchanged;
+        if diff.is_empty() {
+            continue;
+        }
+        println!(
+            "{}: {} added, {} removed, {} changed",
+            bold_underline(format!("{} ({})", diff.contact, diff.conversation_id)),
+            diff.added.len(),
+            diff.removed.len(),
+            diff.changed.len()
+        );
+        if args.content {
+            for &message in &diff.added {
+                options.print_message('+', message);
+            }
+            for &message in &diff.removed {
+                options.print_message('-', message);
+            }
+            for changed in &diff.changed {
+                options.print_message('~', changed.new);
+                for field in &changed.fields {
+                    println!(
+                        "    {}: {} -> {}",
+                        field.name,
+                        abbreviate(&field.old.to_string()),
+                        abbreviate(&field.new.to_string())
+                    );
+                }
+            }
+        }
+    }
+    println!(
+        "{} added, {} removed, {} changed, {} unchanged",
+        added, removed, changed, unchanged
+    );
+    Ok(())
+}
+#[derive(clap::Args)]
+struct Stats {
+    /// The input file to read from
+    input_file: PathBuf,
+    /// Print the statistics as JSON instead of a table
+    #[arg(long)]
+    json: bool,
+    /// Only summarize a single conversation
+    #[command(flatten)]
+    conversation: Option<ConversationArgs>,
+}
+fn stats(options: &CommonOptions, args: &Stats) -> anyhow::Result<()> {
+    let log = options.parse_log(&args.input_file)?;
+    let conversations = match &args.conversation {
+        Some(conversation) => vec![conversation.select(&log)?],
+        None => log.conversations(),
+    };
+    let stats = crate::stats::compute(&conversations, &options.date_style);
+    if args.json {
+        println!("{}", ::serde_json::to_string_pretty(&stats)?);
+        return Ok(());
+    }
+    println!("{}", bold_underline("Messages"));
+    println!("  {:<24} {:>8}", "total", stats.messages);
+    for (direction, count) in &stats.by_direction {
+        println!("  {:<24} {:>8}", direction, count);
+    }
+    println!("{}", bold_underline("By contact"));
+    println!(
+        "  {:<32} {:>8} {:>8} {:>8}",
+        "contact", "sent", "received", "total"
+    );
+    for count in &stats.by_contact {
+        println!(
+            "  {:<32} {:>8} {:>8} {:>8}",
+            format!("{} ({})", count.contact, count.conversation),
+            count.sent,
+            count.received,
+            count.total
+        );
+    }
+    println!("{}", bold_underline("By month"));
+    for (month, count) in &stats.by_month {
+        println!("  {:<24} {:>8}", month, count);
+    }
+    println!("{}", bold_underline("By weekday"));
+    for (day, count) in stats.by_weekday.iter().enumerate() {
+        let day = chrono::Weekday::try_from(day as u8).unwrap();
+        println!("  {:<24} {:>8}", day, count);
+    }
+    println!("{}", bold_underline("By hour"));
+    for (hour, count) in stats.by_hour.iter().enumerate() {
+        println!("  {:<24} {:>8}", format!("{:02}:00", hour), count);
+    }
+    println!("{}", bold_underline("Median reply time"));
+    let latency = |seconds: Option<i64>| {
+        seconds.map_or("-".to_owned(), |seconds| {
+            crate::utils::format_duration(seconds as u64)
+        })
+    };
+    let replies = &stats.median_reply_seconds;
+    println!("  {:<24} {:>8}", "sent", latency(replies.sent));
+    println!("  {:<24} {:>8}", "received", latency(replies.received));
+    println!("{}", bold_underline("Longest gaps"));
+    for gap in &stats.longest_gaps {
+        println!(
+            "  {:<24} {:>8} ({} to {})",
+            gap.contact,
+            crate::utils::format_duration(gap.seconds as u64),
+            options.date_style.format_date(gap.from),
+            options.date_style.format_date(gap.to)
+        );
+    }
+    println!("{}", bold_underline("Longest streaks"));
+    for streak in &stats.longest_streaks {
+        println!(
+            "  {:<24} {:>8}",
+            format!(
+                "{} to {}",
+                options.date_style.format_day(streak.first_day),
+                options.date_style.format_day(streak.last_day)
+            ),
+            format!("{} days", streak.days)
+        );
+    }
+    println!("{}", bold_underline("Content"));
+    println!(
+        "  {:<24} {:>8.1}",
+        "average body length", stats.average_body_length
+    );
+    println!("  {:<24} {:>8}", "attachment bytes", stats.attachment_bytes);
+    for (content_type, count) in &stats.mms_content_types {
+        println!("  {:<24} {:>8}", content_type, count);
+    }
+    Ok(())
+}
+#[derive(clap::Args)]
+struct ExportCsv {
+    /// The input file to read from
+    input_file: PathBuf,
+    /// Output CSV file
+    #[arg(long, required = true)]
+    output: PathBuf,
+    /// Only export a single conversation
+    #[command(flatten)]
+    conversation: Option<ConversationArgs>,
+}
+fn export_csv(options: &CommonOptions, args: &ExportCsv) -> anyhow::Result<()> {
+    let log = options.parse_log(&args.input_file)?;
+    let mut messages = match &args.conversation {
+        Some(conversation) => conversation.select(&log)?.messages,
+        None => log.iter().collect(),
+    };
+    messages.sort_by_key(|message| message.date());
+    let file = BufWriter::new(fs::File::create(&args.output)?);
+    crate::export::write_csv(messages.iter().copied(), file)?;
+    log::info!("Exported {} messages", messages.len());
+    Ok(())
+}
+fn calls(options: &CommonOptions, command: CallsCommand) -> anyhow::Result<()> {
+    match command {
+        CallsCommand::RenderHtml {
+            input_file,
+            number,
+            cdn,
+        } => {
+            let log = options.parse_call_log(&input_file)?;
+            let number = number.map(PhoneNumber::new);
+            let mut calls = log
+                .calls
+                .iter()
+                .filter(|call| number.as_ref().is_none_or(|number| call.number == *number))
+                .collect::<Vec<_>>();
+            calls.sort_by_key(|call| call.date);
+            let title = match &number {
+                Some(number) => format!("Calls with {}", number),
+                None => "Calls".to_owned(),
+            };
+            let render_options = RenderOptions {
+                use_cdn: cdn,
+                dates: options.date_style.clone(),
+                ..Default::default()
+            };
+            println!(
+                "{}",
+                crate::html::render_call_log(&title, &calls, &render_options).0
+            );
+        }
+        CallsCommand::ListContacts { input_file } => {
+            let log = options.parse_call_log(&input_file)?;
+            println!("{}", bold_underline("Calls"));
+            for (number, calls) in log.by_number() {
+                let name = calls
+                    .iter()
+                    .rev()
+                    .map(|call| &*call.contact_name)
+                    .find(|&name| name != UNKNOWN_CONTACT_NAME && !name.is_empty())
+                    .unwrap_or(UNKNOWN_CONTACT_NAME);
+                let number = match number.normalized() {
+                    "" => "(private number)",
+                    number => number,
+                };
+                println!(
+                    "  {} - {} ({} calls, {} total)",
+                    name,
+                    number,
+                    calls.len(),
+                    crate::utils::format_duration(calls.iter().map(|call| call.duration).sum())
+                );
+            }
+        }
+        CallsCommand::DumpJson { input_file, output } => {
+            let log = options.parse_call_log(&input_file)?;
             fs::write(output, crate::formatter::to_string_escaped(&log))?;
         }
+        CallsCommand::ToXml { input_file, output } => {
+            let log = options.parse_call_log(&input_file)?;
+            let file = BufWriter::new(fs::File::create(output)?);
+            crate::xml::write_call_log(&log, file)?;
+        }
     }
     Ok(())
 }
 #[derive(clap::Args)]
+struct Merge {
+    /// The input files to merge
+    #[arg(required = true)]
+    input_files: Vec<PathBuf>,
+    /// Output file (XML, JSON or SQLite)
+    #[arg(long, required = true)]
+    output: PathBuf,
+    /// The fields that identify a message, used to detect duplicates
+    #[arg(long, value_enum, value_delimiter = ',', default_values_t = [
+        IdentityField::Address,
+        IdentityField::Date,
+        IdentityField::Body,
+    ])]
+    key: Vec<IdentityField>,
+}
+fn merge(options: &CommonOptions, args: Merge) -> anyhow::Result<()> {
+    let mut merger = crate::merge::Merger::new(args.key);
+    let mut total_duplicates = 0;
+    for input in &args.input_files {
+        let log = options.parse_log(input)?;
+        let stats = merger.add(log);
+        println!(
+            "{}: {} new messages, {} duplicates",
+            input.display(),
+            stats.added,
+            stats.duplicates
+        );
+        total_duplicates += stats.duplicates;
+    }
+    let log = merger.finish();
+    println!(
+        "{} {} messages ({} duplicates dropped)",
+        bold_underline("Total:"),
+        log.sms_messages.len() + log.mms_messages.len(),
+        total_duplicates
+    );
+    options.write_log(&args.output, &log)
+}
+#[derive(clap::Args)]
 struct ListContacts {
     /// The input XML file to read from
     input_file: PathBuf,
 }
 fn list_contacts(options: &CommonOptions, contacts: &ListContacts) -> anyhow::Result<()> {
-    const UNKNOWN_CONTACT_NAME: &str = "(Unknown)";
     let log = options.parse_log(&contacts.input_file)?;
     let contacts = log.list_contacts();
     let mut by_name = HashMap::with_capacity(contacts.len());
@@ -115,40 +708,155 @@ fn list_contacts(options: &CommonOptions, contacts: &ListContacts) -> anyhow::Re
     unnamed_contacts.sort();
     println!("{}", bold_underline("Named contacts"));
     for (name, phones) in named_contacts {
-        println!("  {} - {}", name, phones.iter().join(", "))
+        println!(
+            "  {} - {}",
+            name,
+            phones.iter().map(PhoneNumber::normalized).join(", ")
+        )
     }
     println!("{}", bold_underline("Unnamed contacts"));
     for phone in unnamed_contacts {
-        println!("  {}", phone);
+        println!("  {}", phone.normalized());
+    }
+    println!("{}", bold_underline("Group conversations"));
+    for conversation in log.conversations() {
+        if conversation.is_group() {
+            println!(
+                "  {} - {} ({} messages, conversation {})",
+                conversation.contact_name(),
+                conversation
+                    .participants
+                    .iter()
+                    .map(PhoneNumber::normalized)
+                    .join(", "),
+                conversation.messages.len(),
+                conversation.id()
+            );
+        }
     }
     Ok(())
 }
+/// Shortens long values (like attachment data) to something readable
+fn abbreviate(text: &str) -> Cow<'_, str> {
+    const MAX_CHARS: usize = 60;
+    match text.char_indices().nth(MAX_CHARS) {
+        Some((end, _)) => Cow::Owned(format!("{}...", &text[..end])),
+        None => Cow::Borrowed(text),
+    }
+}
 fn bold_underline<T: AsRef<str>>(text: T) -> String {
     format!("\u{1B}[1;4m{}\u{1B}[0m", text.as_ref())
 }
+fn log_parse_time(path: &Path, start: Instant) {
+    let duration = start.elapsed();
+    log::info!(
+        "Parsed {} in {}s",
+        path.display(),
+        (duration.as_secs() as f64) + ((duration.subsec_millis() as f64) / 1000.0)
+    );
+}
+/// Uses the country code guessed from `path` for every number without one, from now on
+fn assume_country_code(path: &Path, country_code: &str) -> anyhow::Result<()> {
+    log::info!(
+        "Assuming a country code of +{} for numbers without one, based on {} (use --country-code to override)",
+        country_code,
+        path.display()
+    );
+    crate::phone::set_default_country_code(country_code)
+}
 struct CommonOptions {
     verbose: bool,
+    lenient: bool,
+    dates: DateRange,
+    date_style: DateStyle,
 }
 impl CommonOptions {
+    /// Prints a message on a line (or more) of its own, after the `marker`
+    fn print_message(&self, marker: char, message: &dyn TextMessage) {
+        let direction = match (message.sender(), message.participants().len() > 1) {
+            (Some(sender), true) => format!("from {}", sender),
+            _ => message.kind().name().to_owned(),
+        };
+        let date = self
+            .date_style
+            .localize(message.date(), message.readable_date());
+        println!(
+            "{} {} {} [{}] {}",
+            marker,
+            self.date_style.format_date(date),
+            self.date_style.format_time(date),
+            direction,
+            message.body().text().replace('\n', "\n    ")
+        );
+    }
     fn parse_log(&self, path: &Path) -> Result<crate::model::TextLog, anyhow::Error> {
         let start = Instant::now();
-        let mut file = BufReader::new(std::fs::File::open(path)?);
-        let success = match path.extension().and_then(OsStr::to_str) {
-            Some("xml") => {
-                let mut raw_text = String::new();
-                file.read_to_string(&mut raw_text)?;
-                let sanitized = crate::sanitize::cleanup_html_escapes(&raw_text);
-                crate::xml::parse_log(self.verbose, sanitized)
+        let file = BufReader::new(std::fs::File::open(path)?);
+        let mut success = match path.extension().and_then(OsStr::to_str) {
+            Some("xml") => crate::xml::parse_log(self.verbose, self.lenient, file)?,
+            Some("json") => {
+                let mut log = ::serde_json::from_reader(file)?;
+                // Data that was moved out into blobs is stored next to the dump
+                crate::blobs::load(&mut log, path.parent().unwrap_or(Path::new("")))?;
+                log
+            }
+            Some("sqlite" | "db") => crate::sqlite::read_log(path)?,
+            _ => anyhow::bail!("Unable to determine extension of {}", path.display()),
+        };
+        log_parse_time(path, start);
+        if crate::phone::default_country_code().is_none() {
+            // A dump records the country code it was written with, so its ids stay the same
+            let guess = success
+                .country_code
+                .clone()
+                .or_else(|| crate::phone::infer_country_code(success.numbers()));
+            if let Some(guess) = guess {
+                assume_country_code(path, &guess)?;
+                success.renormalize(&guess);
             }
+        }
+        let country_code = crate::phone::default_country_code();
+        if success.country_code.as_deref() != country_code {
+            log::warn!(
+                "{} was written with a country code of {}, but it's now {}, so its ids will change",
+                path.display(),
+                success.country_code.as_deref().unwrap_or("(none)"),
+                country_code.unwrap_or("(none)")
+            );
+            success.country_code = country_code.map(String::from);
+        }
+        self.dates.filter_log(&mut success, &self.date_style);
+        Ok(success)
+    }
+    fn parse_call_log(&self, path: &Path) -> Result<crate::model::CallLog, anyhow::Error> {
+        let start = Instant::now();
+        let file = BufReader::new(std::fs::File::open(path)?);
+        let mut success = match path.extension().and_then(OsStr::to_str) {
+            Some("xml") => crate::xml::parse_call_log(self.verbose, self.lenient, file)?,
             Some("json") => ::serde_json::from_reader(file)?,
             _ => anyhow::bail!("Unable to determine extension of {}", path.display()),
         };
-        let duration = start.elapsed();
-        log::info!(
-            "Parsed {} in {}s",
-            path.display(),
-            (duration.as_secs() as f64) + ((duration.subsec_millis() as f64) / 1000.0)
-        );
+        log_parse_time(path, start);
+        if crate::phone::default_country_code().is_none() {
+            let numbers = success.calls.iter().map(|call| &call.number);
+            if let Some(guess) = crate::phone::infer_country_code(numbers) {
+                assume_country_code(path, &guess)?;
+                success.renormalize(&guess);
+            }
+        }
+        self.dates.filter_calls(&mut success, &self.date_style);
         Ok(success)
     }
+    fn write_log(&self, path: &Path, log: &crate::model::TextLog) -> Result<(), anyhow::Error> {
+        match path.extension().and_then(OsStr::to_str) {
+            Some("xml") => {
+                let file = BufWriter::new(fs::File::create(path)?);
+                crate::xml::write_log(log, file)?;
+            }
+            Some("json") => fs::write(path, crate::formatter::to_string_escaped(log))?,
+            Some("sqlite" | "db") => crate::sqlite::write_log(log, path)?,
+            _ => anyhow::bail!("Unable to determine extension of {}", path.display()),
+        }
+        Ok(())
+    }
 }
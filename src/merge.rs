@@ -0,0 +1,136 @@
+//! Merges several backups into one, dropping the messages they have in common
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+
+use chrono::{DateTime, Utc};
+
+use crate::model::{BodyKind, TextLog, TextMessage};
+
+/// A field that's part of the identity of a message
+///
+/// Two messages with the same identity are considered duplicates.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum IdentityField {
+    /// The phone number
+    Address,
+    /// The date the message was sent/received
+    Date,
+    /// A hash of the body (or of all the parts, for MMS)
+    Body,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct Identity {
+    address: Option<String>,
+    date: Option<DateTime<Utc>>,
+    body: Option<u64>,
+}
+
+/// How many messages a single input contributed to the merge
+#[derive(Copy, Clone, Debug, Default)]
+pub struct MergeStats {
+    /// The number of messages that were new
+    pub added: usize,
+    /// The number of messages we already had
+    pub duplicates: usize,
+}
+
+pub struct Merger {
+    key: Vec<IdentityField>,
+    seen: HashSet<Identity>,
+    log: TextLog,
+}
+impl Merger {
+    pub fn new(key: Vec<IdentityField>) -> Self {
+        Merger {
+            key,
+            seen: HashSet::new(),
+            log: TextLog::default(),
+        }
+    }
+    /// Adds every message in `log` that we haven't already seen
+    pub fn add(&mut self, log: TextLog) -> MergeStats {
+        let mut stats = MergeStats::default();
+        for sms in log.sms_messages {
+            if self.seen.insert(self.identity(&sms)) {
+                self.log.sms_messages.push(sms);
+                stats.added += 1;
+            } else {
+                stats.duplicates += 1;
+            }
+        }
+        for mms in log.mms_messages {
+            if self.seen.insert(self.identity(&mms)) {
+                self.log.mms_messages.push(mms);
+                stats.added += 1;
+            } else {
+                stats.duplicates += 1;
+            }
+        }
+        stats
+    }
+    /// Finishes the merge, sorting all the messages by date
+    pub fn finish(mut self) -> TextLog {
+        self.log.sms_messages.sort_by_key(|sms| sms.date);
+        self.log.mms_messages.sort_by_key(|mms| mms.date);
+        self.log
+    }
+    fn identity(&self, message: &dyn TextMessage) -> Identity {
+        let has = |field| self.key.contains(&field);
+        Identity {
+            address: has(IdentityField::Address).then(|| message.address().normalized().to_owned()),
+            date: has(IdentityField::Date).then(|| message.date()),
+            body: has(IdentityField::Body).then(|| hash_body(message)),
+        }
+    }
+}
+
+fn hash_body(message: &dyn TextMessage) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    match message.body() {
+        BodyKind::Sms(text) => {
+            "sms".hash(&mut hasher);
+            text.hash(&mut hasher);
+        }
+        BodyKind::Mms { parts } => {
+            "mms".hash(&mut hasher);
+            for part in parts {
+                part.content_type.hash(&mut hasher);
+                part.text.hash(&mut hasher);
+                part.data.hash(&mut hasher);
+            }
+        }
+    }
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{IdentityField, Merger};
+    use crate::model::TextLog;
+    use crate::xml::testing::{log, sent};
+
+    /// Two texts to Alice, the second of which says `again`
+    fn backup(again: &str) -> TextLog {
+        log([
+            sent("+15551234567", "Alice", "2018-07-31 01:20", "Hello"),
+            sent("+15551234567", "Alice", "2018-07-31 01:21", again),
+        ])
+    }
+
+    #[test]
+    fn test_duplicates() {
+        let mut merger = Merger::new(vec![IdentityField::Address, IdentityField::Date]);
+        let first = merger.add(backup("Again"));
+        assert_eq!((first.added, first.duplicates), (2, 0));
+        let second = merger.add(backup("Edited"));
+        assert_eq!((second.added, second.duplicates), (0, 2));
+        let mut merger = Merger::new(vec![IdentityField::Address, IdentityField::Body]);
+        merger.add(backup("Again"));
+        let third = merger.add(backup("Edited"));
+        assert_eq!((third.added, third.duplicates), (1, 1));
+        assert_eq!(merger.finish().sms_messages.len(), 3);
+    }
+}
@@ -1,35 +1,151 @@
-use std::collections::{HashMap, HashSet};
-use std::fmt::{self, Display};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 
 use chrono::{DateTime, Utc};
+use itertools::Itertools;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
-#[derive(Clone, Debug, Eq, Hash, Ord, PartialOrd, PartialEq, Serialize, Deserialize)]
-pub struct PhoneNumber(pub String);
-impl Display for PhoneNumber {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.write_str(&self.0)
-    }
-}
+use crate::blobs::BlobRef;
+pub use crate::phone::PhoneNumber;
+
+/// The contact name the app uses for numbers that aren't in the address book
+pub const UNKNOWN_CONTACT_NAME: &str = "(Unknown)";
 
 pub trait TextMessage {
     fn address(&self) -> &PhoneNumber;
+    /// Everyone (other than ourselves) this message was exchanged with
+    ///
+    /// For group messages, this is more than just the [TextMessage::address].
+    fn participants(&self) -> Vec<PhoneNumber>;
+    /// Who sent this message, or `None` if we sent it
+    fn sender(&self) -> Option<&PhoneNumber>;
     fn contact_name(&self) -> &str;
     fn date(&self) -> DateTime<Utc>;
     fn readable_date(&self) -> &str;
     fn kind(&self) -> MessageKind;
     fn body(&self) -> BodyKind<'_>;
+    /// A short identifier for this message, derived from its content
+    ///
+    /// This stays the same across backups, since it only depends on who the message was with,
+    /// when it was sent, its [kind](MessageKind::name) and what it said
+    /// (and not on things like the contact name or readable date).
+    /// The kind keeps a draft or failed attempt apart from the copy that was actually sent.
+    /// Numbers are compared once [normalized](PhoneNumber::normalized),
+    /// so it does depend on the default country code, which is recorded in dumps for that reason.
+    fn id(&self) -> String {
+        let mut hasher = Sha256::new();
+        for participant in self.participants().iter().collect::<BTreeSet<_>>() {
+            hasher.update(participant.normalized());
+            hasher.update(b"\n");
+        }
+        hasher.update(self.date().timestamp_millis().to_le_bytes());
+        hasher.update(self.kind().name());
+        hasher.update(b"\n");
+        match self.body() {
+            BodyKind::Sms(text) => {
+                hasher.update(b"sms\n");
+                hasher.update(text);
+            }
+            BodyKind::Mms { parts } => {
+                hasher.update(b"mms\n");
+                for part in parts {
+                    hasher.update(&part.content_type);
+                    hasher.update(b"\n");
+                    hasher.update(part.text.as_deref().unwrap_or_default());
+                    hasher.update(b"\n");
+                    // Whether or not the data has been moved out into a blob
+                    let data_hash = match (&part.data, &part.blob) {
+                        (Some(data), _) => crate::utils::to_hex(&Sha256::digest(data)),
+                        (None, Some(blob)) => blob.sha256.clone(),
+                        (None, None) => String::new(),
+                    };
+                    hasher.update(data_hash);
+                    hasher.update(b"\n");
+                }
+            }
+        }
+        crate::utils::to_hex(&hasher.finalize()[..8])
+    }
+    /// The [id](Conversation::id) of the conversation this message belongs to
+    fn conversation_id(&self) -> String {
+        conversation_id(&self.participants().into_iter().collect())
+    }
 }
 pub enum BodyKind<'a> {
     Sms(&'a str),
     Mms { parts: &'a [MmsMessagePart] },
 }
+impl<'a> BodyKind<'a> {
+    /// The text of the message, with the text of every MMS part on its own line
+    pub fn text(&self) -> Cow<'a, str> {
+        match *self {
+            BodyKind::Sms(text) => Cow::Borrowed(text),
+            BodyKind::Mms { parts } => Cow::Owned(
+                parts
+                    .iter()
+                    .filter(|part| part.content_type == "text/plain")
+                    .filter_map(|part| part.text.as_deref())
+                    .join("\n"),
+            ),
+        }
+    }
+    /// The parts which aren't text (or the SMIL that lays them out)
+    pub fn attachments(&self) -> impl Iterator<Item = &'a MmsMessagePart> + 'a {
+        let parts = match *self {
+            BodyKind::Sms(_) => &[][..],
+            BodyKind::Mms { parts } => parts,
+        };
+        parts
+            .iter()
+            .filter(|part| !matches!(&*part.content_type, "text/plain" | "application/smil"))
+    }
+}
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct TextLog {
+    /// The [default country code](crate::phone::set_default_country_code)
+    /// the ids in a dump were derived from
+    ///
+    /// Since that changes which numbers are the same, it changes the ids too.
+    #[serde(default)]
+    pub country_code: Option<String>,
+    #[serde(serialize_with = "serialize_with_ids")]
     pub sms_messages: Vec<SmsMessage>,
+    #[serde(serialize_with = "serialize_with_ids")]
     pub mms_messages: Vec<MmsMessage>,
 }
+/// Includes the ids of each message in JSON dumps, for the benefit of other tools
+///
+/// They're ignored when the dump is read back in, since they're derived from the rest.
+fn serialize_with_ids<M, S>(messages: &[M], serializer: S) -> Result<S::Ok, S::Error>
+where
+    M: TextMessage + Serialize,
+    S: serde::Serializer,
+{
+    #[derive(Serialize)]
+    struct WithIds<'a, M> {
+        id: String,
+        conversation_id: String,
+        #[serde(flatten)]
+        message: &'a M,
+    }
+    serializer.collect_seq(messages.iter().map(|message| WithIds {
+        id: message.id(),
+        conversation_id: message.conversation_id(),
+        message,
+    }))
+}
+impl Default for TextLog {
+    /// An empty log, using the current default country code
+    fn default() -> Self {
+        TextLog {
+            country_code: crate::phone::default_country_code().map(String::from),
+            sms_messages: Vec::new(),
+            mms_messages: Vec::new(),
+        }
+    }
+}
 impl TextLog {
     //noinspection RsNeedlessLifetimes
     pub fn iter(&self) -> impl Iterator<Item = &dyn TextMessage> + '_ {
@@ -42,22 +158,150 @@ impl TextLog {
                     .map(|message| message as &dyn TextMessage),
             )
     }
+    /// Every phone number in the log, including each recipient of a group message
+    pub fn numbers(&self) -> impl Iterator<Item = &PhoneNumber> + '_ {
+        let sms = self.sms_messages.iter().map(|sms| &sms.address);
+        let mms = self.mms_messages.iter().flat_map(|mms| {
+            std::iter::once(&mms.address).chain(mms.addrs.iter().map(|addr| &addr.address))
+        });
+        sms.chain(mms)
+    }
+    /// Normalizes every number again, assuming `country_code` for those without one
+    ///
+    /// This records the new country code, since the ids change along with it.
+    pub fn renormalize(&mut self, country_code: &str) {
+        for sms in &mut self.sms_messages {
+            sms.address.renormalize(Some(country_code));
+        }
+        for mms in &mut self.mms_messages {
+            mms.address.renormalize(Some(country_code));
+            for addr in &mut mms.addrs {
+                addr.address.renormalize(Some(country_code));
+            }
+        }
+        self.country_code = Some(country_code.into());
+    }
     pub fn list_contacts(&self) -> HashMap<PhoneNumber, HashSet<String>> {
         let mut result = HashMap::with_capacity(self.sms_messages.len() + self.mms_messages.len());
-        for sms in &self.sms_messages {
-            result
-                .entry(sms.address.clone())
-                .or_insert_with(HashSet::new)
-                .insert(sms.contact_name.clone());
-        }
-        for mms in &self.mms_messages {
-            result
-                .entry(mms.address.clone())
-                .or_insert_with(HashSet::new)
-                .insert(mms.contact_name.clone());
+        for message in self.iter() {
+            let participants = message.participants();
+            // Group messages have a comma-separated name for each participant
+            let names = if participants.len() == 1 {
+                vec![message.contact_name()]
+            } else {
+                message.contact_name().split(", ").collect()
+            };
+            let names_match = names.len() == participants.len();
+            for (index, number) in participants.into_iter().enumerate() {
+                let entry = result.entry(number).or_insert_with(HashSet::new);
+                if names_match {
+                    entry.insert(names[index].to_owned());
+                }
+            }
         }
         result
     }
+    /// Groups the messages by the set of people they were exchanged with
+    pub fn conversations(&self) -> Vec<Conversation<'_>> {
+        let mut by_participants = BTreeMap::<BTreeSet<PhoneNumber>, Vec<&dyn TextMessage>>::new();
+        for message in self.iter() {
+            by_participants
+                .entry(message.participants().into_iter().collect())
+                .or_default()
+                .push(message);
+        }
+        by_participants
+            .into_iter()
+            .map(|(participants, mut messages)| {
+                messages.sort_by_key(|message| message.date());
+                Conversation {
+                    participants,
+                    messages,
+                }
+            })
+            .collect()
+    }
+}
+
+/// The messages exchanged with a particular group of people
+pub struct Conversation<'a> {
+    /// Everyone in the conversation (other than ourselves)
+    pub participants: BTreeSet<PhoneNumber>,
+    /// The messages in the conversation, sorted by date
+    pub messages: Vec<&'a dyn TextMessage>,
+}
+impl Conversation<'_> {
+    /// The most recent name of the contact (or contacts)
+    pub fn contact_name(&self) -> &str {
+        self.messages
+            .last()
+            .map_or("", |message| message.contact_name())
+    }
+    /// Every name the contact (or contacts) went by
+    pub fn contact_names(&self) -> BTreeSet<&str> {
+        self.messages
+            .iter()
+            .map(|message| message.contact_name())
+            .collect()
+    }
+    #[inline]
+    pub fn is_group(&self) -> bool {
+        self.participants.len() > 1
+    }
+    /// A short identifier for this conversation, derived from its participants
+    ///
+    /// This stays the same across backups,
+    /// as long as they're read with the same default country code.
+    #[inline]
+    pub fn id(&self) -> String {
+        conversation_id(&self.participants)
+    }
+}
+/// The [id](Conversation::id) of the conversation with the given participants
+pub fn conversation_id(participants: &BTreeSet<PhoneNumber>) -> String {
+    let mut hasher = Sha256::new();
+    for participant in participants {
+        hasher.update(participant.normalized());
+        hasher.update(b"\n");
+    }
+    crate::utils::to_hex(&hasher.finalize()[..6])
+}
+impl<'a> Conversation<'a> {
+    /// The messages in this conversation, interleaved with any calls with the contact
+    ///
+    /// Calls are never included in group conversations.
+    pub fn timeline(&self, calls: &'a [CallRecord]) -> Vec<TimelineItem<'a>> {
+        let mut items = self
+            .messages
+            .iter()
+            .map(|&message| TimelineItem::Message(message))
+            .collect::<Vec<_>>();
+        if !self.is_group() {
+            items.extend(
+                calls
+                    .iter()
+                    .filter(|call| self.participants.contains(&call.number))
+                    .map(TimelineItem::Call),
+            );
+        }
+        items.sort_by_key(TimelineItem::date);
+        items
+    }
+}
+
+/// Something that happened in a conversation
+#[derive(Copy, Clone)]
+pub enum TimelineItem<'a> {
+    Message(&'a dyn TextMessage),
+    Call(&'a CallRecord),
+}
+impl TimelineItem<'_> {
+    pub fn date(&self) -> DateTime<Utc> {
+        match self {
+            TimelineItem::Message(message) => message.date(),
+            TimelineItem::Call(call) => call.date,
+        }
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
@@ -78,13 +322,164 @@ pub struct MmsMessage {
     pub kind: MessageKind,
     /// The parts of this MMS message
     pub parts: Vec<MmsMessagePart>,
+    /// The addresses of everyone involved in this message
+    #[serde(default)]
+    pub addrs: Vec<MmsAddress>,
+    /// The remaining MMS headers
+    #[serde(default)]
+    pub headers: MmsHeaders,
+    /// Any attributes we don't otherwise understand
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub extra: BTreeMap<String, String>,
+}
+/// The MMS headers stored by android
+///
+/// These are named after the columns in android's MMS database,
+/// which is also what the backup uses.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MmsHeaders {
+    /// The subject of the message
+    pub sub: Option<String>,
+    /// The charset of the subject
+    pub sub_cs: Option<i32>,
+    /// The message id
+    pub m_id: Option<String>,
+    /// The transaction id
+    pub tr_id: Option<String>,
+    /// The content type of the whole message
+    pub ct_t: Option<String>,
+    /// The content class
+    pub ct_cls: Option<i32>,
+    /// The content location
+    pub ct_l: Option<String>,
+    /// The message class (`personal`, `advertisement`, ...)
+    pub m_cls: Option<String>,
+    /// The message type (for example 128 = send request, 132 = retrieve confirmation)
+    pub m_type: Option<i32>,
+    /// The MMS version
+    pub v: Option<i32>,
+    /// The size of the message, in bytes
+    pub m_size: Option<i64>,
+    /// The priority
+    pub pri: Option<i32>,
+    /// Whether a read report was requested
+    pub rr: Option<i32>,
+    /// The status of the read report
+    pub read_status: Option<i32>,
+    /// Whether a delivery report was requested
+    pub d_rpt: Option<i32>,
+    /// Whether reports are allowed
+    pub rpt_a: Option<i32>,
+    /// The delivery time
+    pub d_tm: Option<i64>,
+    /// The expiry time
+    pub exp: Option<i64>,
+    /// The status of the message
+    pub st: Option<i32>,
+    /// The retrieve status
+    pub retr_st: Option<i32>,
+    /// The retrieve text
+    pub retr_txt: Option<String>,
+    /// The charset of the retrieve text
+    pub retr_txt_cs: Option<i32>,
+    /// The response status
+    pub resp_st: Option<i32>,
+    /// The response text
+    pub resp_txt: Option<String>,
+    /// The package name of the app that created the message
+    pub creator: Option<String>,
+    /// The subscription (SIM) the message was sent/received with
+    pub sub_id: Option<i32>,
+    /// Whether we've read the message
+    pub read: bool,
+    /// Whether we've seen the message
+    pub seen: bool,
+    /// Whether the message is locked (protected from deletion)
+    pub locked: bool,
+    /// Whether the message only contains text
+    pub text_only: bool,
+    /// Whether the callback number was set
+    pub callback_set: Option<i32>,
+}
+/// An entry in the `<addrs>` of an MMS message
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MmsAddress {
+    /// The phone number
+    pub address: PhoneNumber,
+    /// The `type` of address
+    pub role: AddressRole,
+    /// The charset of the address
+    pub charset: Option<i32>,
+    /// Any attributes we don't otherwise understand
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub extra: BTreeMap<String, String>,
 }
+/// The role of an address in an MMS message
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AddressRole {
+    From,
+    To,
+    Cc,
+    Bcc,
+    /// A type we don't know about
+    Other(i32),
+}
+impl AddressRole {
+    /// Determines the role from the numeric `type` in the backup
+    pub fn from_code(code: i32) -> AddressRole {
+        match code {
+            137 => AddressRole::From,
+            151 => AddressRole::To,
+            130 => AddressRole::Cc,
+            129 => AddressRole::Bcc,
+            _ => AddressRole::Other(code),
+        }
+    }
+    /// The numeric `type` used in the backup
+    pub fn code(self) -> i32 {
+        match self {
+            AddressRole::From => 137,
+            AddressRole::To => 151,
+            AddressRole::Cc => 130,
+            AddressRole::Bcc => 129,
+            AddressRole::Other(code) => code,
+        }
+    }
+}
+/// The placeholder android uses for our own address in MMS messages
+pub const SELF_ADDRESS_TOKEN: &str = "insert-address-token";
 impl TextMessage for MmsMessage {
     #[inline]
     fn address(&self) -> &PhoneNumber {
         &self.address
     }
 
+    /// The `~` separated numbers in the address
+    fn participants(&self) -> Vec<PhoneNumber> {
+        self.address
+            .raw()
+            .split('~')
+            .map(PhoneNumber::new)
+            .collect()
+    }
+
+    fn sender(&self) -> Option<&PhoneNumber> {
+        if let MessageKind::Received { .. } = self.kind {
+            self.addrs
+                .iter()
+                .find(|addr| {
+                    addr.role == AddressRole::From && addr.address.raw() != SELF_ADDRESS_TOKEN
+                })
+                .map(|addr| &addr.address)
+                // Older backups don't include the addresses
+                .or(Some(&self.address))
+        } else {
+            None
+        }
+    }
+
     #[inline]
     fn contact_name(&self) -> &str {
         &self.contact_name
@@ -122,6 +517,72 @@ pub struct MmsMessagePart {
     /// The binary data of this message part
     #[serde(with = "crate::utils::base64_opt")]
     pub data: Option<Vec<u8>>,
+    /// The name of this part
+    #[serde(default)]
+    pub name: Option<String>,
+    /// The charset of this part's text
+    #[serde(default)]
+    pub charset: Option<i32>,
+    /// The content disposition
+    #[serde(default)]
+    pub content_disposition: Option<String>,
+    /// The filename of this part
+    #[serde(default)]
+    pub filename: Option<String>,
+    /// The content id, used to refer to this part from the SMIL
+    #[serde(default)]
+    pub content_id: Option<String>,
+    /// The `start` parameter of the content type (`ctt_s`)
+    #[serde(default)]
+    pub ctt_s: Option<String>,
+    /// The `type` parameter of the content type (`ctt_t`)
+    #[serde(default)]
+    pub ctt_t: Option<String>,
+    /// Any attributes we don't otherwise understand
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub extra: BTreeMap<String, String>,
+    /// Where the data is stored, if it's been moved out into a blob
+    ///
+    /// This is only ever set in JSON dumps, and is resolved when they're loaded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blob: Option<BlobRef>,
+}
+impl MmsMessagePart {
+    /// The file name to use when saving this part's data, if it has any
+    ///
+    /// This is derived from a hash of the data, so identical attachments get the same name.
+    pub fn attachment_file_name(&self) -> Option<String> {
+        let data = self.data.as_ref()?;
+        let hash = Sha256::digest(data);
+        Some(format!(
+            "{}.{}",
+            crate::utils::to_hex(&hash[..8]),
+            self.extension()
+        ))
+    }
+    /// The usual file extension for this part's content type
+    pub fn extension(&self) -> &str {
+        match &*self.content_type {
+            "image/jpeg" => "jpg",
+            "image/png" => "png",
+            "image/gif" => "gif",
+            "audio/amr" => "amr",
+            "video/mp4" => "mp4",
+            "video/3gpp" => "3gp",
+            "text/plain" => "txt",
+            "text/x-vCard" | "text/vcard" => "vcf",
+            _ => self
+                .filename
+                .as_deref()
+                .or(self.name.as_deref())
+                .and_then(|name| name.rsplit_once('.'))
+                .map(|(_, extension)| extension)
+                .filter(|extension| {
+                    !extension.is_empty() && extension.bytes().all(|b| b.is_ascii_alphanumeric())
+                })
+                .unwrap_or("bin"),
+        }
+    }
 }
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct SmsMessage {
@@ -141,6 +602,36 @@ pub struct SmsMessage {
     pub kind: MessageKind,
     /// The body of this SMS message
     pub body: String,
+    /// The protocol identifier
+    #[serde(default)]
+    pub protocol: Option<i32>,
+    /// The subject of the message (almost always absent)
+    #[serde(default)]
+    pub subject: Option<String>,
+    /// The type of address of the sender
+    #[serde(default)]
+    pub toa: Option<String>,
+    /// The type of address of the service center
+    #[serde(default)]
+    pub sc_toa: Option<String>,
+    /// The service center the message was received through
+    #[serde(default)]
+    pub service_center: Option<String>,
+    /// Whether we've read the message
+    #[serde(default)]
+    pub read: bool,
+    /// The delivery status (-1 = none, 0 = complete, 32 = pending, 64 = failed)
+    #[serde(default)]
+    pub status: Option<i32>,
+    /// Whether the message is locked (protected from deletion)
+    #[serde(default)]
+    pub locked: bool,
+    /// The subscription (SIM) the message was sent/received with
+    #[serde(default)]
+    pub sub_id: Option<i32>,
+    /// Any attributes we don't otherwise understand
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub extra: BTreeMap<String, String>,
 }
 impl TextMessage for SmsMessage {
     #[inline]
@@ -148,6 +639,19 @@ impl TextMessage for SmsMessage {
         &self.address
     }
 
+    #[inline]
+    fn participants(&self) -> Vec<PhoneNumber> {
+        vec![self.address.clone()]
+    }
+
+    #[inline]
+    fn sender(&self) -> Option<&PhoneNumber> {
+        match self.kind {
+            MessageKind::Received { .. } => Some(&self.address),
+            _ => None,
+        }
+    }
+
     #[inline]
     fn contact_name(&self) -> &str {
         &self.contact_name
@@ -181,4 +685,269 @@ pub enum MessageKind {
         /// Date they claimed to send the text (date is when we actually received it)
         date_sent: DateTime<Utc>,
     },
+    /// A message that was written but never sent
+    Draft,
+    /// A message that is currently being sent
+    Outbox,
+    /// A message that we failed to send
+    Failed,
+    /// A message that is waiting to be sent
+    Queued,
+}
+impl MessageKind {
+    /// The name of this kind, like "received" or "draft"
+    pub fn name(self) -> &'static str {
+        match self {
+            MessageKind::Sent => "sent",
+            MessageKind::Received { .. } => "received",
+            MessageKind::Draft => "draft",
+            MessageKind::Outbox => "outbox",
+            MessageKind::Failed => "failed",
+            MessageKind::Queued => "queued",
+        }
+    }
+}
+
+/// A backup of the call log (the `calls-*.xml` files)
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct CallLog {
+    pub calls: Vec<CallRecord>,
+}
+impl CallLog {
+    /// All the calls, grouped by number
+    pub fn by_number(&self) -> BTreeMap<&PhoneNumber, Vec<&CallRecord>> {
+        let mut result = BTreeMap::<&PhoneNumber, Vec<&CallRecord>>::new();
+        for call in &self.calls {
+            result.entry(&call.number).or_default().push(call);
+        }
+        result
+    }
+    /// Normalizes every number again, assuming `country_code` for those without one
+    pub fn renormalize(&mut self, country_code: &str) {
+        for call in &mut self.calls {
+            call.number.renormalize(Some(country_code));
+        }
+    }
+}
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CallRecord {
+    /// The phone number of the other party
+    pub number: PhoneNumber,
+    /// How long the call lasted, in seconds
+    pub duration: u64,
+    /// The date the call started
+    pub date: DateTime<Utc>,
+    pub kind: CallKind,
+    /// Whether the caller id was shown
+    pub presentation: Presentation,
+    /// The name of the contact
+    pub contact_name: String,
+    /// The human-readable version of `date`
+    pub readable_date: String,
+    /// Any attributes we don't otherwise understand
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub extra: BTreeMap<String, String>,
+}
+/// The `type` of a call
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CallKind {
+    Incoming,
+    Outgoing,
+    Missed,
+    Voicemail,
+    Rejected,
+    Blocked,
+}
+impl CallKind {
+    /// Determines the kind from the numeric `type` in the backup
+    pub fn from_code(code: i32) -> Option<CallKind> {
+        Some(match code {
+            1 => CallKind::Incoming,
+            2 => CallKind::Outgoing,
+            3 => CallKind::Missed,
+            4 => CallKind::Voicemail,
+            5 => CallKind::Rejected,
+            6 => CallKind::Blocked,
+            _ => return None,
+        })
+    }
+    /// The numeric `type` used in the backup
+    pub fn code(self) -> i32 {
+        match self {
+            CallKind::Incoming => 1,
+            CallKind::Outgoing => 2,
+            CallKind::Missed => 3,
+            CallKind::Voicemail => 4,
+            CallKind::Rejected => 5,
+            CallKind::Blocked => 6,
+        }
+    }
+    /// A human readable description, like "Missed call"
+    pub fn description(self) -> &'static str {
+        match self {
+            CallKind::Incoming => "Incoming call",
+            CallKind::Outgoing => "Outgoing call",
+            CallKind::Missed => "Missed call",
+            CallKind::Voicemail => "Voicemail",
+            CallKind::Rejected => "Rejected call",
+            CallKind::Blocked => "Blocked call",
+        }
+    }
+}
+/// Whether the caller id of a call was available
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Presentation {
+    Allowed,
+    Restricted,
+    Unknown,
+    Payphone,
+    /// A presentation we don't know about
+    Other(i32),
+}
+impl Presentation {
+    /// Determines the presentation from the numeric value in the backup
+    pub fn from_code(code: i32) -> Presentation {
+        match code {
+            1 => Presentation::Allowed,
+            2 => Presentation::Restricted,
+            3 => Presentation::Unknown,
+            4 => Presentation::Payphone,
+            _ => Presentation::Other(code),
+        }
+    }
+    /// The numeric value used in the backup
+    pub fn code(self) -> i32 {
+        match self {
+            Presentation::Allowed => 1,
+            Presentation::Restricted => 2,
+            Presentation::Unknown => 3,
+            Presentation::Payphone => 4,
+            Presentation::Other(code) => code,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{PhoneNumber, TextLog, TextMessage, TimelineItem};
+    use crate::xml::testing::{addr_from, addr_to, log, received, received_mms, sent};
+
+    const ALICE: &str = "+15551234567";
+    const BOB: &str = "+15559876543";
+
+    /// Two texts with Alice, with a group message from Bob (to her and us) in between
+    fn alice_and_bob() -> TextLog {
+        let group = received_mms(
+            &format!("{}~{}", ALICE, BOB),
+            "Alice, Bob",
+            "2018-07-31 01:21",
+            &[],
+            &[
+                addr_from(BOB),
+                addr_to(ALICE),
+                addr_to("insert-address-token"),
+            ],
+        );
+        log([
+            sent(ALICE, "Alice", "2018-07-31 01:20", "Hello"),
+            group,
+            received(ALICE, "Alice", "2018-07-31 01:22", "Hi"),
+        ])
+    }
+
+    #[test]
+    fn test_group_conversations() {
+        let log = alice_and_bob();
+        let contacts = log.list_contacts();
+        assert_eq!(contacts.len(), 2);
+        assert!(contacts[&PhoneNumber::new("+15559876543")].contains("Bob"));
+        let conversations = log.conversations();
+        assert_eq!(conversations.len(), 2);
+        assert!(!conversations[0].is_group());
+        assert_eq!(conversations[0].messages.len(), 2);
+        assert!(conversations[1].is_group());
+        let group_message = conversations[1].messages[0];
+        assert_eq!(group_message.sender().unwrap().raw(), "+15559876543");
+    }
+
+    #[test]
+    fn test_guessed_country_code() {
+        let mut log = log([
+            sent(ALICE, "Alice", "2018-07-31 01:20", "Hello"),
+            received("(555) 123-4567", "Alice", "2018-07-31 01:22", "Hi"),
+        ]);
+        assert_eq!(log.list_contacts().len(), 2);
+        let guess = crate::phone::infer_country_code(log.numbers()).unwrap();
+        assert_eq!(guess, "1");
+        log.renormalize(&guess);
+        assert_eq!(log.country_code.as_deref(), Some("1"));
+        let contacts = log.list_contacts();
+        assert_eq!(contacts.len(), 1);
+        assert_eq!(contacts[&PhoneNumber::new(ALICE)].len(), 1);
+        assert_eq!(log.conversations()[0].messages.len(), 2);
+    }
+
+    #[test]
+    fn test_timeline() {
+        let log = alice_and_bob();
+        let calls = crate::xml::parse_call_log(
+            false,
+            false,
+            r#"<calls count="2">
+  <call number="+15551234567" duration="60" date="1533000090000" type="2" presentation="1" readable_date="" contact_name="Alice" />
+  <call number="+15559876543" duration="0" date="1533000090000" type="3" presentation="1" readable_date="" contact_name="Bob" />
+</calls>"#
+                .as_bytes(),
+        )
+        .unwrap();
+        let conversations = log.conversations();
+        let timeline = conversations[0].timeline(&calls.calls);
+        assert_eq!(timeline.len(), 3);
+        assert!(matches!(timeline[1], TimelineItem::Call(call) if call.duration == 60));
+        // Calls don't belong to group conversations
+        assert_eq!(conversations[1].timeline(&calls.calls).len(), 1);
+    }
+
+    #[test]
+    fn test_ids() {
+        let ids = |records: &[String]| {
+            let log = log(records);
+            log.iter().map(|message| message.id()).collect::<Vec<_>>()
+        };
+        let hello = sent(ALICE, "Alice", "2018-07-31 01:20", "Hello");
+        let hi = received(ALICE, "Alice", "2018-07-31 01:22", "Hi");
+        let original = ids(&[hello.clone(), hi]);
+        assert_ne!(original[0], original[1]);
+        // Neither the contact name nor the readable date matter
+        let renamed = [
+            sent(ALICE, "Alice Smith", "2018-07-31 01:20", "Hello")
+                .replace(r#"readable_date="""#, r#"readable_date="Jul 30, 2018""#),
+            received(ALICE, "Alice Smith", "2018-07-31 01:22", "Hi"),
+        ];
+        assert_eq!(ids(&renamed), original);
+        let edited = received(ALICE, "Alice", "2018-07-31 01:22", "Hey");
+        assert_ne!(ids(&[edited]), original[1..]);
+        // A failed attempt isn't the same message as the one that was sent
+        let failed = hello.replace(r#"type="2""#, r#"type="5""#);
+        assert_ne!(ids(&[failed]), original[..1]);
+
+        let log = alice_and_bob();
+        let ids = log.iter().map(|message| message.id()).collect::<Vec<_>>();
+        assert_eq!(ids.len(), 3);
+        let conversations = log.conversations();
+        assert_eq!(log.sms_messages[0].conversation_id(), conversations[0].id());
+        assert_eq!(log.mms_messages[0].conversation_id(), conversations[1].id());
+
+        let json = serde_json::to_value(&log).unwrap();
+        assert_eq!(json["country_code"], serde_json::Value::Null);
+        assert_eq!(json["sms_messages"][1]["id"], *ids[1]);
+        assert_eq!(
+            json["mms_messages"][0]["conversation_id"],
+            conversations[1].id()
+        );
+        let loaded: TextLog = serde_json::from_value(json).unwrap();
+        assert_eq!(loaded.sms_messages, log.sms_messages);
+    }
 }
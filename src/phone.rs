@@ -0,0 +1,263 @@
+//! Phone numbers, and normalizing the many ways of writing them
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+use std::hash::{Hash, Hasher};
+use std::sync::OnceLock;
+
+use serde::{Deserialize, Serialize};
+
+static DEFAULT_COUNTRY_CODE: OnceLock<String> = OnceLock::new();
+
+/// Sets the country code assumed for numbers without one
+///
+/// This must be called before any numbers are parsed,
+/// and can only be called once.
+pub fn set_default_country_code(code: &str) -> Result<(), anyhow::Error> {
+    let code = code.strip_prefix('+').unwrap_or(code);
+    if code.is_empty() || code.len() > 3 || !code.bytes().all(|b| b.is_ascii_digit()) {
+        anyhow::bail!("Invalid country code: {:?}", code);
+    }
+    DEFAULT_COUNTRY_CODE
+        .set(code.into())
+        .map_err(|_| anyhow::anyhow!("Default country code already set"))
+}
+
+/// The country code assumed for numbers without one, if there is one
+#[inline]
+pub fn default_country_code() -> Option<&'static str> {
+    DEFAULT_COUNTRY_CODE.get().map(String::as_str)
+}
+
+/// A phone number (or other address) exactly as it appeared in the backup
+///
+/// Numbers are compared by their [normalized](PhoneNumber::normalized) form,
+/// so `+15551234567` and `(555) 123-4567` are the same number
+/// (assuming the default country code is 1).
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub struct PhoneNumber {
+    raw: String,
+    normalized: String,
+}
+impl PhoneNumber {
+    pub fn new(raw: impl Into<String>) -> PhoneNumber {
+        PhoneNumber::with_country_code(raw, default_country_code())
+    }
+    /// Like [PhoneNumber::new], but with an explicit default country code
+    pub fn with_country_code(raw: impl Into<String>, country_code: Option<&str>) -> PhoneNumber {
+        let raw = raw.into();
+        let normalized = normalize(&raw, country_code);
+        PhoneNumber { raw, normalized }
+    }
+    /// Normalizes the number again, with a different default country code
+    pub fn renormalize(&mut self, country_code: Option<&str>) {
+        self.normalized = normalize(&self.raw, country_code);
+    }
+    /// The number exactly as it appeared in the backup
+    #[inline]
+    pub fn raw(&self) -> &str {
+        &self.raw
+    }
+    /// The number in E.164 format, if it's an ordinary phone number
+    ///
+    /// Short codes, alphanumeric sender ids and other odd addresses are left alone.
+    #[inline]
+    pub fn normalized(&self) -> &str {
+        &self.normalized
+    }
+}
+impl From<String> for PhoneNumber {
+    #[inline]
+    fn from(raw: String) -> Self {
+        PhoneNumber::new(raw)
+    }
+}
+impl From<PhoneNumber> for String {
+    #[inline]
+    fn from(number: PhoneNumber) -> Self {
+        number.raw
+    }
+}
+impl Display for PhoneNumber {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.raw)
+    }
+}
+impl PartialEq for PhoneNumber {
+    #[inline]
+    fn eq(&self, other: &Self) -> bool {
+        self.normalized == other.normalized
+    }
+}
+impl Eq for PhoneNumber {}
+impl Hash for PhoneNumber {
+    #[inline]
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.normalized.hash(state)
+    }
+}
+impl PartialOrd for PhoneNumber {
+    #[inline]
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for PhoneNumber {
+    #[inline]
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.normalized.cmp(&other.normalized)
+    }
+}
+
+/// Guesses the default country code, from the most common one among numbers that have one
+///
+/// Returns `None` if none of the numbers have a country code.
+pub fn infer_country_code<'a>(
+    numbers: impl IntoIterator<Item = &'a PhoneNumber>,
+) -> Option<String> {
+    let mut counts = HashMap::<String, usize>::new();
+    for code in numbers
+        .into_iter()
+        .filter_map(|number| country_code_of(&number.raw))
+    {
+        *counts.entry(code).or_insert(0) += 1;
+    }
+    // Ties go to the smallest code, so the guess doesn't depend on the order of the numbers
+    counts
+        .into_iter()
+        .min_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then_with(|| a.cmp(b)))
+        .map(|(code, _)| code)
+}
+
+/// Country codes with two digits
+///
+/// Country codes never start with each other, so every other code starting with 2 to 9 has three
+/// digits, while 1 (North America) and 7 (Russia and Kazakhstan) have just the one.
+const TWO_DIGIT_COUNTRY_CODES: &[&str] = &[
+    "20", "27", "30", "31", "32", "33", "34", "36", "39", "40", "41", "43", "44", "45", "46", "47",
+    "48", "49", "51", "52", "53", "54", "55", "56", "57", "58", "60", "61", "62", "63", "64", "65",
+    "66", "81", "82", "84", "86", "90", "91", "92", "93", "94", "95", "98",
+];
+
+/// The country code of a number written in international format
+fn country_code_of(raw: &str) -> Option<String> {
+    let normalized = normalize(raw, None);
+    let digits = normalized.strip_prefix('+')?;
+    // Too short to be a whole international number
+    if digits.len() <= MAX_SHORT_CODE_LEN {
+        return None;
+    }
+    let len = if digits.starts_with(['1', '7']) {
+        1
+    } else if TWO_DIGIT_COUNTRY_CODES.contains(&&digits[..2]) {
+        2
+    } else {
+        3
+    };
+    Some(digits[..len].into())
+}
+
+/// The longest number we consider to be a short code
+const MAX_SHORT_CODE_LEN: usize = 6;
+
+/// Normalizes a phone number into E.164 format
+///
+/// National numbers are assumed to be in the `country_code`, if one is given.
+/// Anything that doesn't look like a phone number is returned unchanged.
+pub fn normalize(raw: &str, country_code: Option<&str>) -> String {
+    let trimmed = raw.trim();
+    let (international, rest) = match trimmed.strip_prefix('+') {
+        Some(rest) => (true, rest),
+        None => (false, trimmed),
+    };
+    if rest.is_empty()
+        || !rest
+            .chars()
+            .all(|c| c.is_ascii_digit() || matches!(c, ' ' | '-' | '.' | '(' | ')' | '/'))
+    {
+        // Alphanumeric sender ids, emails, group addresses, etc
+        return raw.into();
+    }
+    let digits = rest
+        .chars()
+        .filter(char::is_ascii_digit)
+        .collect::<String>();
+    if international {
+        return format!("+{}", digits);
+    }
+    if let Some(digits) = digits.strip_prefix("00") {
+        return format!("+{}", digits);
+    }
+    if digits.len() <= MAX_SHORT_CODE_LEN {
+        return digits;
+    }
+    match country_code {
+        // The North American Numbering Plan uses a trunk prefix of 1 (the same as the country code)
+        Some("1") if digits.len() == 11 && digits.starts_with('1') => format!("+{}", digits),
+        Some("1") if digits.starts_with("011") => format!("+{}", &digits[3..]),
+        // Most other countries use a trunk prefix of 0
+        Some(code) => format!("+{}{}", code, digits.strip_prefix('0').unwrap_or(&digits)),
+        None => digits,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{infer_country_code, normalize, PhoneNumber};
+
+    #[test]
+    fn test_normalize() {
+        for raw in [
+            "+15551234567",
+            "+1 (555) 123-4567",
+            "(555) 123-4567",
+            "5551234567",
+            "1-555-123-4567",
+            "555.123.4567",
+        ] {
+            assert_eq!(normalize(raw, Some("1")), "+15551234567", "{:?}", raw);
+        }
+        assert_eq!(normalize("(555) 123-4567", None), "5551234567");
+        assert_eq!(normalize("020 7946 0018", Some("44")), "+442079460018");
+        assert_eq!(normalize("0044 20 7946 0018", Some("1")), "+442079460018");
+    }
+
+    #[test]
+    fn test_infer_country_code() {
+        let numbers = [
+            "+15551234567",
+            "(555) 123-4567",
+            "+1 555 987 6543",
+            "+44 20 7946 0018",
+        ]
+        .map(|raw| PhoneNumber::with_country_code(raw, None));
+        assert_eq!(infer_country_code(&numbers).as_deref(), Some("1"));
+        let numbers = [
+            "00442079460018",
+            "+353 1 234 5678",
+            "+353 87 123 4567",
+            "22000",
+        ]
+        .map(|raw| PhoneNumber::with_country_code(raw, None));
+        assert_eq!(infer_country_code(&numbers).as_deref(), Some("353"));
+        let numbers =
+            ["5551234567", "AMAZON", "+2"].map(|raw| PhoneNumber::with_country_code(raw, None));
+        assert_eq!(infer_country_code(&numbers), None);
+    }
+
+    #[test]
+    fn test_unusual_addresses() {
+        // Short codes
+        assert_eq!(normalize("22000", Some("1")), "22000");
+        assert_eq!(normalize("#3", Some("1")), "#3");
+        // Alphanumeric sender ids
+        assert_eq!(normalize("VM-HDFCBK", Some("91")), "VM-HDFCBK");
+        assert_eq!(normalize("AMAZON", Some("1")), "AMAZON");
+        assert_eq!(
+            normalize("someone@example.com", Some("1")),
+            "someone@example.com"
+        );
+    }
+}
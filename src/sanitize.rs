@@ -22,30 +22,16 @@ fn decode_utf16_surrogates(low: u32, high: u32) -> char {
 }
 
 /// Replaces HTML escaped utf16 surrogates with their correct counterparts
+///
+/// Anything else, including hex escapes and surrogates without a pair,
+/// is passed through unchanged for the XML parser to deal with.
 pub fn cleanup_html_escapes(s: &str) -> String {
     let mut result = String::with_capacity(s.len());
     let mut remaining = s;
     while !remaining.is_empty() {
-        if remaining.starts_with("&#") {
-            let end = remaining.find(';').unwrap();
-            let escape = &remaining[..=end];
-            let escape_number = parse_escape_number(escape);
-            remaining = &remaining[end + 1..];
-            if HIGH_SURROGATES.contains(&escape_number) {
-                assert!(
-                    remaining.starts_with("&#"),
-                    "Invalid remaining after surrogate: {}",
-                    &remaining[..5]
-                );
-                let low_end = remaining.find(';').unwrap();
-                let low_escape = &remaining[..=low_end];
-                let low_number = parse_escape_number(low_escape);
-                remaining = &remaining[(low_end + 1)..];
-                let c = decode_utf16_surrogates(low_number, escape_number);
-                result.push_str(&format!("&#x{:X};", c as u32))
-            } else {
-                result.push_str(escape)
-            }
+        if let Some((c, len)) = parse_surrogate_pair(remaining) {
+            result.push_str(&format!("&#x{:X};", c as u32));
+            remaining = &remaining[len..];
         } else {
             let c = remaining.chars().next().unwrap();
             result.push(c);
@@ -55,14 +41,80 @@ pub fn cleanup_html_escapes(s: &str) -> String {
     result
 }
 
-fn parse_escape_number(escape: &str) -> u32 {
-    u32::from_str(&escape[2..escape.len() - 1])
-        .unwrap_or_else(|_| panic!("Invalid numeric escape: {:?}", escape))
+/// Decodes a pair of escaped surrogates at the start of `s`,
+/// returning the character and the length of both escapes
+fn parse_surrogate_pair(s: &str) -> Option<(char, usize)> {
+    let (high, high_len) = parse_escape(s)?;
+    if !HIGH_SURROGATES.contains(&high) {
+        return None;
+    }
+    let (low, low_len) = parse_escape(&s[high_len..])?;
+    if !LOW_SURROGATES.contains(&low) {
+        return None;
+    }
+    Some((decode_utf16_surrogates(low, high), high_len + low_len))
+}
+
+/// Parses a decimal numeric escape at the start of `s`, returning its number and length
+fn parse_escape(s: &str) -> Option<(u32, usize)> {
+    let digits = s.strip_prefix("&#")?;
+    let end = digits.find(';')?;
+    let number = u32::from_str(&digits[..end]).ok()?;
+    Some((number, end + 3))
+}
+
+/// Incrementally applies [cleanup_html_escapes] to a stream of text chunks
+///
+/// Escapes (or surrogate pairs of escapes) that are split across chunks
+/// are held back until the rest of them arrives.
+#[derive(Default)]
+pub struct EscapeCleaner {
+    pending: String,
+}
+impl EscapeCleaner {
+    /// Cleans up the next chunk of input, appending the result to `out`
+    pub fn push(&mut self, chunk: &str, out: &mut String) {
+        self.pending.push_str(chunk);
+        let complete = complete_prefix_len(&self.pending);
+        out.push_str(&cleanup_html_escapes(&self.pending[..complete]));
+        self.pending.drain(..complete);
+    }
+    /// Flushes any input that was held back waiting for the rest of an escape
+    pub fn finish(&mut self, out: &mut String) {
+        out.push_str(&cleanup_html_escapes(&self.pending));
+        self.pending.clear();
+    }
+}
+
+/// The length of the prefix of `s` that doesn't end in a partial escape
+/// or in a high surrogate which is still waiting for its pair.
+fn complete_prefix_len(s: &str) -> usize {
+    let mut end = s.len();
+    // A trailing '&' could be the start of an escape
+    if s.ends_with('&') {
+        end -= 1;
+    }
+    if let Some(start) = s[..end].rfind("&#") {
+        // Only something that could still turn into an escape is held back
+        let rest = &s[start + 2..end];
+        if rest.len() <= 8 && rest.bytes().all(|b| b.is_ascii_alphanumeric()) {
+            end = start;
+        }
+    }
+    match s[..end].rfind("&#") {
+        Some(start) if is_high_surrogate_escape(&s[start..end]) => start,
+        _ => end,
+    }
+}
+
+fn is_high_surrogate_escape(escape: &str) -> bool {
+    parse_escape(escape)
+        .is_some_and(|(number, len)| len == escape.len() && HIGH_SURROGATES.contains(&number))
 }
 
 #[cfg(test)]
 mod test {
-    use super::cleanup_html_escapes;
+    use super::{cleanup_html_escapes, EscapeCleaner};
     #[test]
     fn test_basic() {
         assert_eq!(
@@ -76,4 +128,41 @@ mod test {
             "Same to you! &#x1F386;&#x1F1FA;&#x1F1F2; asdf-testing;;"
         )
     }
+    #[test]
+    fn test_passthrough() {
+        // Hex escapes are already fine
+        assert_eq!(
+            cleanup_html_escapes("&#x263A; &#x1F60A;"),
+            "&#x263A; &#x1F60A;"
+        );
+        // Surrogates without their pair are left for the parser
+        assert_eq!(cleanup_html_escapes("a &#55357; b"), "a &#55357; b");
+        assert_eq!(
+            cleanup_html_escapes("&#55357;&#55357;x"),
+            "&#55357;&#55357;x"
+        );
+        assert_eq!(cleanup_html_escapes("&#56842;&#55357;"), "&#56842;&#55357;");
+        assert_eq!(cleanup_html_escapes("&#55357;"), "&#55357;");
+        assert_eq!(cleanup_html_escapes("&# &#; &#abc"), "&# &#; &#abc");
+        let mut cleaner = EscapeCleaner::default();
+        let mut result = String::new();
+        cleaner.push("x &#55357;", &mut result);
+        cleaner.push(" y &#", &mut result);
+        cleaner.finish(&mut result);
+        assert_eq!(result, "x &#55357; y &#");
+    }
+    #[test]
+    fn test_chunked() {
+        let text = "??? Whoop whoop! &#55357;&#56842; asdf &amp; &#55356;&#57222;";
+        let expected = cleanup_html_escapes(text);
+        for chunk_size in 1..text.len() {
+            let mut cleaner = EscapeCleaner::default();
+            let mut result = String::new();
+            for chunk in text.as_bytes().chunks(chunk_size) {
+                cleaner.push(std::str::from_utf8(chunk).unwrap(), &mut result);
+            }
+            cleaner.finish(&mut result);
+            assert_eq!(result, expected, "chunk_size = {}", chunk_size);
+        }
+    }
 }
@@ -0,0 +1,111 @@
+//! Finds messages by their text
+
+use std::ops::Range;
+
+use regex::{Regex, RegexBuilder};
+
+use crate::model::{Conversation, TextMessage};
+
+/// Decides whether the text of a message matches the query
+pub enum Matcher {
+    /// A case-insensitive substring, which has already been lowercased
+    Substring(String),
+    Regex(Regex),
+}
+impl Matcher {
+    pub fn substring(query: &str) -> Matcher {
+        Matcher::Substring(query.to_lowercase())
+    }
+    pub fn regex(pattern: &str, case_sensitive: bool) -> Result<Matcher, regex::Error> {
+        RegexBuilder::new(pattern)
+            .case_insensitive(!case_sensitive)
+            .build()
+            .map(Matcher::Regex)
+    }
+    pub fn is_match(&self, message: &dyn TextMessage) -> bool {
+        let text = message.body().text();
+        match self {
+            Matcher::Substring(query) => text.to_lowercase().contains(query),
+            Matcher::Regex(regex) => regex.is_match(&text),
+        }
+    }
+}
+
+/// The messages in a conversation that matched, along with their context
+pub struct SearchResult<'a, 'b> {
+    pub conversation: &'b Conversation<'a>,
+    /// The indexes of the messages which matched
+    pub hits: Vec<usize>,
+    /// The ranges of messages to show, which each include at least one hit
+    ///
+    /// These never overlap, and are in order.
+    pub ranges: Vec<Range<usize>>,
+}
+
+/// Searches every conversation for messages that match,
+/// including `context` messages either side of each hit
+pub fn search<'a, 'b>(
+    conversations: &'b [Conversation<'a>],
+    matcher: &Matcher,
+    context: usize,
+) -> Vec<SearchResult<'a, 'b>> {
+    conversations
+        .iter()
+        .filter_map(|conversation| {
+            let hits = conversation
+                .messages
+                .iter()
+                .enumerate()
+                .filter(|&(_, &message)| matcher.is_match(message))
+                .map(|(index, _)| index)
+                .collect::<Vec<_>>();
+            if hits.is_empty() {
+                return None;
+            }
+            let mut ranges = Vec::<Range<usize>>::new();
+            for &hit in &hits {
+                let range = hit.saturating_sub(context)
+                    ..(hit + context + 1).min(conversation.messages.len());
+                match ranges.last_mut() {
+                    Some(last) if last.end >= range.start => last.end = range.end,
+                    _ => ranges.push(range),
+                }
+            }
+            Some(SearchResult {
+                conversation,
+                hits,
+                ranges,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{search, Matcher};
+    use crate::xml::testing::{log, received, sent};
+
+    #[test]
+    fn test_search() {
+        let alice = "+15551234567";
+        let log = log([
+            sent(alice, "Alice", "2018-07-31 01:20", "Want to get lunch?"),
+            received(alice, "Alice", "2018-07-31 01:21", "Sure"),
+            sent(alice, "Alice", "2018-07-31 01:22", "Where?"),
+            received(alice, "Alice", "2018-07-31 01:23", "The usual"),
+            sent(alice, "Alice", "2018-07-31 01:24", "LUNCH is great"),
+        ]);
+        let conversations = log.conversations();
+        let results = search(&conversations, &Matcher::substring("Lunch"), 1);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].hits, vec![0, 4]);
+        assert_eq!(results[0].ranges, vec![0..2, 3..5]);
+        let results = search(&conversations, &Matcher::substring("lunch"), 2);
+        assert_eq!(results[0].ranges, vec![0..5]);
+        let regex = Matcher::regex("^(sure|where)", false).unwrap();
+        let results = search(&conversations, &regex, 0);
+        assert_eq!(results[0].hits, vec![1, 2]);
+        let regex = Matcher::regex("^lunch", true).unwrap();
+        assert!(search(&conversations, &regex, 0).is_empty());
+    }
+}
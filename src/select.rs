@@ -0,0 +1,211 @@
+//! Picks out a single conversation from a log
+
+use itertools::Itertools;
+
+use crate::model::{Conversation, PhoneNumber, TextLog, UNKNOWN_CONTACT_NAME};
+
+/// The lowest similarity we'll accept for a fuzzy match on a name
+const MIN_SIMILARITY: f64 = 0.85;
+
+/// Selects a single conversation
+///
+/// This is optional by default. Commands which always need a conversation
+/// should make the group required.
+#[derive(clap::Args)]
+#[group(multiple = false)]
+pub struct ConversationArgs {
+    /// The phone number of the contact
+    #[arg(long)]
+    number: Option<String>,
+    /// The name of the contact (case-insensitive, and tolerant of typos)
+    #[arg(long, alias = "contact")]
+    name: Option<String>,
+    /// The id of a conversation, as shown by `list-contacts`
+    #[arg(long)]
+    conversation: Option<String>,
+}
+impl ConversationArgs {
+    pub fn select<'a>(&self, log: &'a TextLog) -> anyhow::Result<Conversation<'a>> {
+        let conversations = log.conversations();
+        if let Some(number) = &self.number {
+            let number = PhoneNumber::new(number.as_str());
+            conversations
+                .into_iter()
+                .find(|conversation| {
+                    !conversation.is_group() && conversation.participants.contains(&number)
+                })
+                .ok_or_else(|| anyhow::anyhow!("No conversation with {}", number.normalized()))
+        } else if let Some(id) = &self.conversation {
+            conversations
+                .into_iter()
+                .find(|conversation| conversation.id() == *id)
+                .ok_or_else(|| anyhow::anyhow!("No conversation with id {:?}", id))
+        } else if let Some(name) = &self.name {
+            select_by_name(conversations, name)
+        } else {
+            unreachable!("clap requires one of the arguments")
+        }
+    }
+}
+
+/// How well a conversation matches a name, from worst to best
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum NameMatch {
+    /// A similar name, along with the similarity (in thousandths)
+    Fuzzy(u32),
+    /// The name contains what we searched for
+    Substring,
+    Exact,
+}
+impl NameMatch {
+    fn new(query: &str, name: &str) -> Option<NameMatch> {
+        let name = name.to_lowercase();
+        if name == query {
+            return Some(NameMatch::Exact);
+        }
+        if name.contains(query) {
+            return Some(NameMatch::Substring);
+        }
+        let similarity = strsim::jaro_winkler(query, &name);
+        (similarity >= MIN_SIMILARITY).then_some(NameMatch::Fuzzy((similarity * 1000.0) as u32))
+    }
+}
+
+fn select_by_name<'a>(
+    conversations: Vec<Conversation<'a>>,
+    name: &str,
+) -> anyhow::Result<Conversation<'a>> {
+    let query = name.trim().to_lowercase();
+    if query.is_empty() {
+        // Every name contains the empty string
+        anyhow::bail!("The name of the contact can't be empty");
+    }
+    let matches = conversations
+        .into_iter()
+        .filter_map(|conversation| {
+            // Contacts may have been renamed at some point, so check every name
+            let best = conversation
+                .contact_names()
+                .into_iter()
+                .filter(|&name| name != UNKNOWN_CONTACT_NAME)
+                .filter_map(|name| NameMatch::new(&query, name))
+                .max()?;
+            Some((best, conversation))
+        })
+        .max_set_by_key(|&(best, _)| best);
+    match matches.len() {
+        0 => anyhow::bail!("No contact named {:?}", name),
+        1 => {
+            let (best, conversation) = matches.into_iter().next().unwrap();
+            if best != NameMatch::Exact {
+                log::info!("Using contact {:?}", conversation.contact_name());
+            }
+            Ok(conversation)
+        }
+        _ => {
+            let candidates = matches
+                .iter()
+                .map(|(_, conversation)| {
+                    format!(
+                        "  {} - {} (conversation {})",
+                        conversation.contact_name(),
+                        conversation
+                            .participants
+                            .iter()
+                            .map(PhoneNumber::normalized)
+                            .join(", "),
+                        conversation.id()
+                    )
+                })
+                .join("\n");
+            anyhow::bail!(
+                "Multiple conversations match {:?}, use --number or --conversation instead:\n{}",
+                name,
+                candidates
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ConversationArgs;
+    use crate::model::TextLog;
+    use crate::xml::testing::{addr_from, addr_to, log, received, received_mms, sent, text_part};
+
+    /// Alice, Alicia and Bob, and a group conversation with Alice and Bob
+    fn contacts() -> TextLog {
+        let (alice, bob) = ("+15551234567", "+15559876543");
+        log([
+            sent(alice, "Alice Smith", "2018-07-31 01:20", "Hi"),
+            sent("+15552222222", "Alicia Keys", "2018-07-31 01:21", "Hi"),
+            received("+15553333333", "(Unknown)", "2018-07-31 01:22", "Hi"),
+            received_mms(
+                &format!("{}~{}", alice, bob),
+                "Alice Smith, Bob Jones",
+                "2018-07-31 01:23",
+                &[text_part("Hi all")],
+                &[addr_from(bob), addr_to(alice)],
+            ),
+            received(bob, "Bob Jones", "2018-07-31 01:24", "Hi"),
+        ])
+    }
+
+    fn select_name(log: &TextLog, name: &str) -> anyhow::Result<String> {
+        let args = ConversationArgs {
+            number: None,
+            name: Some(name.to_owned()),
+            conversation: None,
+        };
+        args.select(log)
+            .map(|conversation| conversation.contact_name().to_owned())
+    }
+
+    #[test]
+    fn test_select_by_name() {
+        let log = contacts();
+        // An exact match beats the group it's a substring of
+        assert_eq!(select_name(&log, "alice SMITH").unwrap(), "Alice Smith");
+        assert_eq!(select_name(&log, "keys").unwrap(), "Alicia Keys");
+        // Typos
+        assert_eq!(select_name(&log, "Alicai Keys").unwrap(), "Alicia Keys");
+        let error = select_name(&log, "ali").unwrap_err().to_string();
+        assert!(error.starts_with("Multiple conversations"), "{}", error);
+        assert!(error.contains("Alicia Keys") && error.contains("Alice Smith, Bob Jones"));
+        assert!(select_name(&log, "unknown").is_err());
+        assert!(select_name(&log, "").is_err());
+        assert!(select_name(&log, "  ").is_err());
+    }
+
+    #[test]
+    fn test_select_by_number() {
+        let log = contacts();
+        // Group conversations are skipped, even though they come first
+        let args = ConversationArgs {
+            number: Some("+1 (555) 987-6543".to_owned()),
+            name: None,
+            conversation: None,
+        };
+        let conversation = args.select(&log).unwrap();
+        assert_eq!(conversation.contact_name(), "Bob Jones");
+        assert!(!conversation.is_group());
+
+        let group = log
+            .conversations()
+            .into_iter()
+            .find(|conversation| conversation.is_group())
+            .unwrap();
+        let args = ConversationArgs {
+            number: None,
+            name: None,
+            conversation: Some(group.id()),
+        };
+        assert_eq!(args.select(&log).unwrap().id(), group.id());
+        let args = ConversationArgs {
+            number: None,
+            name: None,
+            conversation: Some("000000000000".to_owned()),
+        };
+        assert!(args.select(&log).is_err());
+    }
+}
@@ -0,0 +1,120 @@
+//! Renders every conversation in a backup into a directory of linked pages
+
+use std::cmp::Reverse;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use itertools::Itertools;
+use maud::{html, Markup};
+
+use crate::html::{AttachmentMode, RenderOptions};
+use crate::model::{BodyKind, Conversation, PhoneNumber, TextLog, TextMessage};
+
+/// The name of the page in each directory
+const PAGE_NAME: &str = "index.html";
+
+/// Writes an index of every conversation to `output`,
+/// along with a page for each conversation
+///
+/// Each conversation gets its own directory (named by its id),
+/// and its attachments are written next to its page.
+/// Attachments are always linked, whatever the `options` say.
+/// Returns the number of conversations written.
+pub fn render_site(log: &TextLog, output: &Path, options: RenderOptions) -> io::Result<usize> {
+    let options = RenderOptions {
+        attachments: AttachmentMode::Linked,
+        ..options
+    };
+    let mut conversations = log.conversations();
+    // Most recently active first, like on the phone
+    conversations.sort_by_key(|conversation| Reverse(last_message(conversation).date()));
+    fs::create_dir_all(output)?;
+    for conversation in &conversations {
+        let dir = output.join(conversation.id());
+        fs::create_dir_all(&dir)?;
+        write_attachments(conversation, &dir)?;
+        // There's no call log to go with the backup
+        let page = crate::html::render_conversation(
+            conversation,
+            &[],
+            Some(html! { p { a href={ "../" (PAGE_NAME) } { "All conversations" } } }),
+            &options,
+        );
+        fs::write(dir.join(PAGE_NAME), page.into_string())?;
+    }
+    let index = render_index(&conversations, &options);
+    fs::write(output.join(PAGE_NAME), index.into_string())?;
+    Ok(conversations.len())
+}
+
+/// Writes the data of every MMS part, skipping any we've already written
+fn write_attachments(conversation: &Conversation<'_>, dir: &Path) -> io::Result<()> {
+    for message in &conversation.messages {
+        let BodyKind::Mms { parts } = message.body() else {
+            continue;
+        };
+        for part in parts {
+            if let (Some(name), Some(data)) = (part.attachment_file_name(), &part.data) {
+                let path = dir.join(name);
+                if !path.exists() {
+                    fs::write(path, data)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn render_index(conversations: &[Conversation<'_>], options: &RenderOptions) -> Markup {
+    crate::html::render_page(
+        "Conversations",
+        options,
+        html! {
+            div class="container" {
+                h3 class="text-center" { "Conversations" }
+                table class="table" {
+                    thead {
+                        tr {
+                            th { "Contact" }
+                            th { "Participants" }
+                            th { "Messages" }
+                            th { "First message" }
+                            th { "Last message" }
+                        }
+                    }
+                    tbody {
+                        @for conversation in conversations {
+                            tr {
+                                td {
+                                    a href={ (conversation.id()) "/" (PAGE_NAME) } {
+                                        (crate::html::conversation_title(conversation))
+                                    }
+                                }
+                                td {
+                                    (conversation.participants.iter().map(PhoneNumber::normalized).join(", "))
+                                }
+                                td { (conversation.messages.len()) }
+                                td { (render_short_date(conversation.messages[0], options)) }
+                                td { (render_short_date(last_message(conversation), options)) }
+                            }
+                        }
+                    }
+                }
+            }
+        },
+    )
+}
+
+#[inline]
+fn last_message<'a>(conversation: &Conversation<'a>) -> &'a dyn TextMessage {
+    // Conversations are never empty
+    conversation.messages.last().copied().unwrap()
+}
+
+fn render_short_date(message: &dyn TextMessage, options: &RenderOptions) -> String {
+    let date = options
+        .dates
+        .localize(message.date(), message.readable_date());
+    options.dates.format_date(date)
+}
@@ -0,0 +1,462 @@
+//! Stores a log in a SQLite database, which can be queried with plain SQL
+//!
+//! Every table has ordinary columns for the things worth querying,
+//! while the rest of each record is kept as JSON so that nothing is lost
+//! when the database is read back in.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+
+use chrono::{DateTime, TimeZone, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+use crate::model::{
+    AddressRole, MessageKind, MmsAddress, MmsHeaders, MmsMessage, MmsMessagePart, PhoneNumber,
+    SmsMessage, TextLog, TextMessage,
+};
+
+/// The schema, which is created from scratch whenever a log is written
+///
+/// Dates are milliseconds since the unix epoch, just like in the XML backups.
+const SCHEMA: &str = "
+-- Anything the ids were derived from, like the 'country_code'
+CREATE TABLE settings (
+    name TEXT PRIMARY KEY,
+    value TEXT
+);
+CREATE TABLE conversations (
+    id TEXT PRIMARY KEY,
+    contact_name TEXT NOT NULL,
+    is_group INTEGER NOT NULL
+);
+CREATE TABLE conversation_participants (
+    conversation_id TEXT NOT NULL REFERENCES conversations(id),
+    number TEXT NOT NULL,
+    PRIMARY KEY (conversation_id, number)
+);
+-- Every name each (normalized) number went by
+CREATE TABLE contacts (
+    number TEXT NOT NULL,
+    name TEXT NOT NULL,
+    PRIMARY KEY (number, name)
+);
+CREATE TABLE messages (
+    id INTEGER PRIMARY KEY,
+    conversation_id TEXT NOT NULL REFERENCES conversations(id),
+    -- Either 'sms' or 'mms'
+    type TEXT NOT NULL,
+    -- 'sent', 'received', 'draft', 'outbox', 'failed' or 'queued'
+    kind TEXT NOT NULL,
+    address TEXT NOT NULL,
+    contact_name TEXT NOT NULL,
+    date INTEGER NOT NULL,
+    date_sent INTEGER,
+    readable_date TEXT NOT NULL,
+    -- The text of the message (including every text part of an MMS)
+    body TEXT NOT NULL,
+    details TEXT NOT NULL
+);
+CREATE INDEX messages_by_conversation ON messages (conversation_id, date);
+CREATE TABLE parts (
+    message_id INTEGER NOT NULL REFERENCES messages(id),
+    seq INTEGER NOT NULL,
+    content_type TEXT NOT NULL,
+    content_location TEXT NOT NULL,
+    name TEXT,
+    filename TEXT,
+    text TEXT,
+    data BLOB,
+    details TEXT NOT NULL
+);
+CREATE INDEX parts_by_message ON parts (message_id);
+-- The addresses of MMS messages
+CREATE TABLE addresses (
+    message_id INTEGER NOT NULL REFERENCES messages(id),
+    address TEXT NOT NULL,
+    number TEXT NOT NULL,
+    -- 'from', 'to', 'cc', 'bcc' or the numeric type
+    role TEXT NOT NULL,
+    charset INTEGER,
+    details TEXT NOT NULL
+);
+CREATE INDEX addresses_by_message ON addresses (message_id);
+CREATE VIRTUAL TABLE messages_fts USING fts5(body, content='messages', content_rowid='id');
+";
+
+/// The parts of an SMS that don't have their own column
+#[derive(Serialize, Deserialize)]
+struct SmsDetails {
+    protocol: Option<i32>,
+    subject: Option<String>,
+    toa: Option<String>,
+    sc_toa: Option<String>,
+    service_center: Option<String>,
+    read: bool,
+    status: Option<i32>,
+    locked: bool,
+    sub_id: Option<i32>,
+    extra: BTreeMap<String, String>,
+}
+/// The parts of an MMS that don't have their own column
+#[derive(Serialize, Deserialize)]
+struct MmsDetails {
+    headers: MmsHeaders,
+    extra: BTreeMap<String, String>,
+}
+/// The parts of an MMS part that don't have their own column
+#[derive(Serialize, Deserialize)]
+struct PartDetails {
+    charset: Option<i32>,
+    content_disposition: Option<String>,
+    content_id: Option<String>,
+    ctt_s: Option<String>,
+    ctt_t: Option<String>,
+    extra: BTreeMap<String, String>,
+}
+
+/// Writes the log into a new database at `path`, replacing anything already there
+pub fn write_log(log: &TextLog, path: &Path) -> anyhow::Result<()> {
+    if path.exists() {
+        fs::remove_file(path)?;
+    }
+    let mut connection = Connection::open(path)?;
+    let transaction = connection.transaction()?;
+    transaction.execute_batch(SCHEMA)?;
+    transaction.execute(
+        "INSERT INTO settings (name, value) VALUES ('country_code', ?1)",
+        params![log.country_code],
+    )?;
+    for conversation in log.conversations() {
+        let id = conversation.id();
+        transaction.execute(
+            "INSERT INTO conversations (id, contact_name, is_group) VALUES (?1, ?2, ?3)",
+            params![id, conversation.contact_name(), conversation.is_group()],
+        )?;
+        for participant in &conversation.participants {
+            transaction.execute(
+                "INSERT INTO conversation_participants (conversation_id, number) VALUES (?1, ?2)",
+                params![id, participant.normalized()],
+            )?;
+        }
+    }
+    for (number, names) in log.list_contacts() {
+        for name in names {
+            transaction.execute(
+                "INSERT INTO contacts (number, name) VALUES (?1, ?2)",
+                params![number.normalized(), name],
+            )?;
+        }
+    }
+    for sms in &log.sms_messages {
+        let details = SmsDetails {
+            protocol: sms.protocol,
+            subject: sms.subject.clone(),
+            toa: sms.toa.clone(),
+            sc_toa: sms.sc_toa.clone(),
+            service_center: sms.service_center.clone(),
+            read: sms.read,
+            status: sms.status,
+            locked: sms.locked,
+            sub_id: sms.sub_id,
+            extra: sms.extra.clone(),
+        };
+        insert_message(&transaction, sms, "sms", &serde_json::to_string(&details)?)?;
+    }
+    for mms in &log.mms_messages {
+        let details = MmsDetails {
+            headers: mms.headers.clone(),
+            extra: mms.extra.clone(),
+        };
+        let message_id =
+            insert_message(&transaction, mms, "mms", &serde_json::to_string(&details)?)?;
+        for part in &mms.parts {
+            let details = PartDetails {
+                charset: part.charset,
+                content_disposition: part.content_disposition.clone(),
+                content_id: part.content_id.clone(),
+                ctt_s: part.ctt_s.clone(),
+                ctt_t: part.ctt_t.clone(),
+                extra: part.extra.clone(),
+            };
+            transaction.execute(
+                "INSERT INTO parts (message_id, seq, content_type, content_location, name, filename, text, data, details)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                params![
+                    message_id,
+                    part.seq,
+                    part.content_type,
+                    part.content_location,
+                    part.name,
+                    part.filename,
+                    part.text,
+                    part.data,
+                    serde_json::to_string(&details)?,
+                ],
+            )?;
+        }
+        for addr in &mms.addrs {
+            transaction.execute(
+                "INSERT INTO addresses (message_id, address, number, role, charset, details)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    message_id,
+                    addr.address.raw(),
+                    addr.address.normalized(),
+                    role_name(addr.role),
+                    addr.charset,
+                    serde_json::to_string(&addr.extra)?,
+                ],
+            )?;
+        }
+    }
+    transaction.execute(
+        "INSERT INTO messages_fts (messages_fts) VALUES ('rebuild')",
+        [],
+    )?;
+    transaction.commit()?;
+    Ok(())
+}
+/// Inserts a row into the messages table, returning its id
+fn insert_message(
+    connection: &Connection,
+    message: &dyn TextMessage,
+    message_type: &str,
+    details: &str,
+) -> rusqlite::Result<i64> {
+    let participants = message.participants().into_iter().collect::<BTreeSet<_>>();
+    let date_sent = match message.kind() {
+        MessageKind::Received { date_sent } => Some(date_sent.timestamp_millis()),
+        _ => None,
+    };
+    connection.execute(
+        "INSERT INTO messages (conversation_id, type, kind, address, contact_name, date, date_sent, readable_date, body, details)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+        params![
+            crate::model::conversation_id(&participants),
+            message_type,
+            message.kind().name(),
+            message.address().raw(),
+            message.contact_name(),
+            message.date().timestamp_millis(),
+            date_sent,
+            message.readable_date(),
+            message.body().text(),
+            details,
+        ],
+    )?;
+    Ok(connection.last_insert_rowid())
+}
+fn role_name(role: AddressRole) -> String {
+    match role {
+        AddressRole::From => "from".into(),
+        AddressRole::To => "to".into(),
+        AddressRole::Cc => "cc".into(),
+        AddressRole::Bcc => "bcc".into(),
+        AddressRole::Other(code) => code.to_string(),
+    }
+}
+
+/// Reads back a log written by [write_log]
+pub fn read_log(path: &Path) -> anyhow::Result<TextLog> {
+    let connection = Connection::open_with_flags(path, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let has_messages = connection
+        .query_row(
+            "SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'messages'",
+            [],
+            |_| Ok(()),
+        )
+        .optional()?;
+    if has_messages.is_none() {
+        anyhow::bail!("{} isn't a database written by smstools", path.display());
+    }
+    let mut log = TextLog {
+        country_code: connection.query_row(
+            "SELECT value FROM settings WHERE name = 'country_code'",
+            [],
+            |row| row.get(0),
+        )?,
+        sms_messages: Vec::new(),
+        mms_messages: Vec::new(),
+    };
+    let mut statement = connection.prepare(
+        "SELECT kind, address, contact_name, date, date_sent, readable_date, body, details
+         FROM messages WHERE type = 'sms' ORDER BY id",
+    )?;
+    let mut rows = statement.query([])?;
+    while let Some(row) = rows.next()? {
+        let details: SmsDetails = serde_json::from_str(&row.get::<_, String>(7)?)?;
+        log.sms_messages.push(SmsMessage {
+            kind: message_kind(&row.get::<_, String>(0)?, row.get(4)?)?,
+            address: PhoneNumber::new(row.get::<_, String>(1)?),
+            contact_name: row.get(2)?,
+            date: from_millis(row.get(3)?)?,
+            readable_date: row.get(5)?,
+            body: row.get(6)?,
+            protocol: details.protocol,
+            subject: details.subject,
+            toa: details.toa,
+            sc_toa: details.sc_toa,
+            service_center: details.service_center,
+            read: details.read,
+            status: details.status,
+            locked: details.locked,
+            sub_id: details.sub_id,
+            extra: details.extra,
+        });
+    }
+    let mut statement = connection.prepare(
+        "SELECT id, kind, address, contact_name, date, date_sent, readable_date, details
+         FROM messages WHERE type = 'mms' ORDER BY id",
+    )?;
+    let mut rows = statement.query([])?;
+    while let Some(row) = rows.next()? {
+        let id: i64 = row.get(0)?;
+        let details: MmsDetails = serde_json::from_str(&row.get::<_, String>(7)?)?;
+        log.mms_messages.push(MmsMessage {
+            kind: message_kind(&row.get::<_, String>(1)?, row.get(5)?)?,
+            address: PhoneNumber::new(row.get::<_, String>(2)?),
+            contact_name: row.get(3)?,
+            date: from_millis(row.get(4)?)?,
+            readable_date: row.get(6)?,
+            parts: read_parts(&connection, id)?,
+            addrs: read_addrs(&connection, id)?,
+            headers: details.headers,
+            extra: details.extra,
+        });
+    }
+    Ok(log)
+}
+fn read_parts(connection: &Connection, message_id: i64) -> anyhow::Result<Vec<MmsMessagePart>> {
+    let mut statement = connection.prepare_cached(
+        "SELECT seq, content_type, content_location, name, filename, text, data, details
+         FROM parts WHERE message_id = ?1 ORDER BY rowid",
+    )?;
+    let mut rows = statement.query([message_id])?;
+    let mut parts = Vec::new();
+    while let Some(row) = rows.next()? {
+        let details: PartDetails = serde_json::from_str(&row.get::<_, String>(7)?)?;
+        parts.push(MmsMessagePart {
+            seq: row.get(0)?,
+            content_type: row.get(1)?,
+            content_location: row.get(2)?,
+            name: row.get(3)?,
+            filename: row.get(4)?,
+            text: row.get(5)?,
+            data: row.get(6)?,
+            charset: details.charset,
+            content_disposition: details.content_disposition,
+            content_id: details.content_id,
+            ctt_s: details.ctt_s,
+            ctt_t: details.ctt_t,
+            extra: details.extra,
+            blob: None,
+        });
+    }
+    Ok(parts)
+}
+fn read_addrs(connection: &Connection, message_id: i64) -> anyhow::Result<Vec<MmsAddress>> {
+    let mut statement = connection.prepare_cached(
+        "SELECT address, role, charset, details FROM addresses WHERE message_id = ?1 ORDER BY rowid",
+    )?;
+    let mut rows = statement.query([message_id])?;
+    let mut addrs = Vec::new();
+    while let Some(row) = rows.next()? {
+        let role: String = row.get(1)?;
+        let role = match &*role {
+            "from" => AddressRole::From,
+            "to" => AddressRole::To,
+            "cc" => AddressRole::Cc,
+            "bcc" => AddressRole::Bcc,
+            code => AddressRole::Other(code.parse()?),
+        };
+        addrs.push(MmsAddress {
+            address: PhoneNumber::new(row.get::<_, String>(0)?),
+            role,
+            charset: row.get(2)?,
+            extra: serde_json::from_str(&row.get::<_, String>(3)?)?,
+        });
+    }
+    Ok(addrs)
+}
+/// The inverse of [MessageKind::name]
+fn message_kind(name: &str, date_sent: Option<i64>) -> anyhow::Result<MessageKind> {
+    Ok(match name {
+        "sent" => MessageKind::Sent,
+        "received" => MessageKind::Received {
+            date_sent: from_millis(date_sent.unwrap_or(0))?,
+        },
+        "draft" => MessageKind::Draft,
+        "outbox" => MessageKind::Outbox,
+        "failed" => MessageKind::Failed,
+        "queued" => MessageKind::Queued,
+        _ => anyhow::bail!("Invalid message kind {:?}", name),
+    })
+}
+fn from_millis(millis: i64) -> anyhow::Result<DateTime<Utc>> {
+    Utc.timestamp_millis_opt(millis)
+        .single()
+        .ok_or_else(|| anyhow::anyhow!("Invalid date {}", millis))
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use rusqlite::Connection;
+
+    use crate::xml::testing::log;
+
+    #[test]
+    fn test_roundtrip() {
+        let path = std::env::temp_dir().join(format!("smstools-{}.sqlite", std::process::id()));
+        // Plenty of attributes that only some messages have
+        let log = log([
+            r#"<sms address="+15551234567" date="1533000000000" type="2" body="Hello there" readable_date="" contact_name="Alice" spam_report="0" />"#,
+            r#"<mms address="+15551234567~+15559876543" date="1533000060000" msg_box="1" date_sent="1533000050000" readable_date="" contact_name="Alice, Bob" sub="Hi">
+    <parts>
+      <part seq="0" ct="image/png" cl="a.png" text="null" data="iVBORw0KGgo=" cid="&lt;a&gt;" />
+      <part seq="0" ct="text/plain" cl="text_0.txt" text="General Kenobi" />
+    </parts>
+    <addrs>
+      <addr address="+15559876543" type="137" charset="106" />
+      <addr address="insert-address-token" type="151" />
+    </addrs>
+  </mms>"#,
+        ]);
+        super::write_log(&log, &path).unwrap();
+        let connection = Connection::open(&path).unwrap();
+        let found: String = connection
+            .query_row(
+                "SELECT contact_name FROM messages_fts JOIN messages ON messages.id = messages_fts.rowid
+                 WHERE messages_fts MATCH 'kenobi'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(found, "Alice, Bob");
+        drop(connection);
+        let reread = super::read_log(&path).unwrap();
+        assert_eq!(reread.sms_messages, log.sms_messages);
+        // Edits to the columns are what's read back in
+        let connection = Connection::open(&path).unwrap();
+        connection
+            .execute(
+                "UPDATE messages SET body = 'Edited', contact_name = 'Carol' WHERE type = 'sms'",
+                [],
+            )
+            .unwrap();
+        drop(connection);
+        let edited = super::read_log(&path).unwrap();
+        assert!(edited.sms_messages.iter().all(|sms| sms.body == "Edited"));
+        assert!(edited
+            .sms_messages
+            .iter()
+            .all(|sms| sms.contact_name == "Carol"));
+        assert_eq!(edited.sms_messages[0].read, log.sms_messages[0].read);
+        assert_eq!(reread.mms_messages, log.mms_messages);
+        assert_eq!(reread.country_code, log.country_code);
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,277 @@
+//! Summarizes how messages were exchanged
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet};
+
+use chrono::{DateTime, Datelike, FixedOffset, NaiveDate, Timelike};
+use serde::Serialize;
+
+use crate::dates::DateStyle;
+use crate::model::{BodyKind, Conversation, MessageKind};
+
+/// How many of the longest gaps and streaks to report
+const TOP_COUNT: usize = 5;
+
+/// Statistics about a set of conversations
+///
+/// Months, weekdays, hours, days and the dates of gaps are in the display timezone.
+#[derive(Debug, Serialize)]
+pub struct Stats {
+    pub messages: usize,
+    /// The number of messages of each [kind](MessageKind::name)
+    pub by_direction: BTreeMap<&'static str, usize>,
+    /// The conversations, with the most messages first
+    pub by_contact: Vec<ContactCount>,
+    /// The number of messages in each month, keyed like `2018-07`
+    pub by_month: BTreeMap<String, usize>,
+    /// The number of messages on each day of the week, starting with Monday
+    pub by_weekday: [usize; 7],
+    /// The number of messages in each hour of the day
+    pub by_hour: [usize; 24],
+    pub median_reply_seconds: ReplyLatency,
+    /// The longest silences within a conversation, longest first
+    pub longest_gaps: Vec<Gap>,
+    /// The longest runs of consecutive days with at least one message, longest first
+    pub longest_streaks: Vec<Streak>,
+    /// The average number of characters in the text of a message
+    pub average_body_length: f64,
+    /// The number of MMS parts with each content type
+    pub mms_content_types: BTreeMap<String, usize>,
+    /// The total size of every attachment, in bytes
+    pub attachment_bytes: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ContactCount {
+    pub contact: String,
+    pub conversation: String,
+    pub sent: usize,
+    pub received: usize,
+    pub total: usize,
+}
+
+/// The median time taken to reply, in either direction
+///
+/// A reply is a message sent straight after one was received (or vice versa)
+/// in the same conversation.
+#[derive(Debug, Default, Serialize)]
+pub struct ReplyLatency {
+    /// How long we took to reply to them
+    pub sent: Option<i64>,
+    /// How long they took to reply to us
+    pub received: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Gap {
+    pub contact: String,
+    pub from: DateTime<FixedOffset>,
+    pub to: DateTime<FixedOffset>,
+    pub seconds: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Streak {
+    pub first_day: NaiveDate,
+    pub last_day: NaiveDate,
+    pub days: u64,
+}
+
+pub fn compute(conversations: &[Conversation<'_>], style: &DateStyle) -> Stats {
+    let mut by_direction = BTreeMap::new();
+    let mut by_contact = Vec::with_capacity(conversations.len());
+    let mut by_month = BTreeMap::new();
+    let mut by_weekday = [0; 7];
+    let mut by_hour = [0; 24];
+    let (mut sent_replies, mut received_replies) = (Vec::new(), Vec::new());
+    let mut gaps = Vec::new();
+    let mut days = BTreeSet::new();
+    let mut body_length = 0;
+    let mut mms_content_types = BTreeMap::new();
+    let mut attachment_bytes = 0;
+    let mut messages = 0;
+    for conversation in conversations {
+        let contact = crate::html::conversation_title(conversation);
+        let mut count = ContactCount {
+            contact: contact.clone(),
+            conversation: conversation.id(),
+            sent: 0,
+            received: 0,
+            total: conversation.messages.len(),
+        };
+        for &message in &conversation.messages {
+            let kind = message.kind();
+            *by_direction.entry(kind.name()).or_insert(0) += 1;
+            match kind {
+                MessageKind::Sent => count.sent += 1,
+                MessageKind::Received { .. } => count.received += 1,
+                _ => {}
+            }
+            let date = style.localize(message.date(), message.readable_date());
+            *by_month
+                .entry(date.format("%Y-%m").to_string())
+                .or_insert(0) += 1;
+            by_weekday[date.weekday().num_days_from_monday() as usize] += 1;
+            by_hour[date.hour() as usize] += 1;
+            days.insert(date.date_naive());
+            let body = message.body();
+            body_length += body.text().chars().count();
+            if let BodyKind::Mms { parts } = body {
+                for part in parts {
+                    *mms_content_types
+                        .entry(part.content_type.clone())
+                        .or_insert(0) += 1;
+                }
+            }
+            attachment_bytes += body
+                .attachments()
+                .map(|part| match (&part.data, &part.blob) {
+                    (Some(data), _) => data.len() as u64,
+                    (None, Some(blob)) => blob.size,
+                    (None, None) => 0,
+                })
+                .sum::<u64>();
+        }
+        messages += conversation.messages.len();
+        for pair in conversation.messages.windows(2) {
+            let (previous, next) = (pair[0], pair[1]);
+            let seconds = (next.date() - previous.date()).num_seconds();
+            gaps.push(Gap {
+                contact: contact.clone(),
+                from: style.localize(previous.date(), previous.readable_date()),
+                to: style.localize(next.date(), next.readable_date()),
+                seconds,
+            });
+            match (previous.kind(), next.kind()) {
+                (MessageKind::Received { .. }, MessageKind::Sent) => sent_replies.push(seconds),
+                (MessageKind::Sent, MessageKind::Received { .. }) => received_replies.push(seconds),
+                _ => {}
+            }
+        }
+        by_contact.push(count);
+    }
+    by_contact.sort_by_key(|count| Reverse(count.total));
+    gaps.sort_by_key(|gap| Reverse(gap.seconds));
+    gaps.truncate(TOP_COUNT);
+    Stats {
+        messages,
+        by_direction,
+        by_contact,
+        by_month,
+        by_weekday,
+        by_hour,
+        median_reply_seconds: ReplyLatency {
+            sent: median(&mut sent_replies),
+            received: median(&mut received_replies),
+        },
+        longest_gaps: gaps,
+        longest_streaks: streaks(&days),
+        average_body_length: if messages > 0 {
+            body_length as f64 / messages as f64
+        } else {
+            0.0
+        },
+        mms_content_types,
+        attachment_bytes,
+    }
+}
+
+fn median(values: &mut [i64]) -> Option<i64> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_unstable();
+    let middle = values.len() / 2;
+    Some(if values.len().is_multiple_of(2) {
+        (values[middle - 1] + values[middle]) / 2
+    } else {
+        values[middle]
+    })
+}
+
+/// The longest runs of consecutive days
+fn streaks(days: &BTreeSet<NaiveDate>) -> Vec<Streak> {
+    let mut streaks = Vec::<Streak>::new();
+    for &day in days {
+        match streaks.last_mut() {
+            Some(streak) if streak.last_day.succ_opt() == Some(day) => {
+                streak.last_day = day;
+                streak.days += 1;
+            }
+            _ => streaks.push(Streak {
+                first_day: day,
+                last_day: day,
+                days: 1,
+            }),
+        }
+    }
+    // Stable, so ties keep the earliest streak first
+    streaks.sort_by_key(|streak| Reverse(streak.days));
+    streaks.truncate(TOP_COUNT);
+    streaks
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::NaiveDate;
+
+    use crate::dates::{DateStyle, DisplayTimezone};
+    use crate::xml::testing::{data_part, log, received, sent, sent_mms, text_part};
+
+    #[test]
+    fn test_stats() {
+        let (alice, bob) = ("+15551234567", "+15559876543");
+        let parts = [
+            data_part("image/png", "a.png", "iVBORw0KGgo="),
+            text_part("Look"),
+        ];
+        let log = log([
+            sent(alice, "Alice", "2018-07-31 01:20", "Want to get lunch?"),
+            received(alice, "Alice", "2018-07-31 01:21", "Sure"),
+            sent(alice, "Alice", "2018-08-01 01:20", "Again?"),
+            received(alice, "Alice", "2018-08-01 01:25", "No"),
+            received(bob, "Bob", "2018-08-03 01:20", "Hi"),
+            sent_mms(bob, "Bob", "2018-08-03 01:21", &parts),
+        ]);
+        let style = DateStyle {
+            timezone: DisplayTimezone::Named(chrono_tz::UTC),
+            ..Default::default()
+        };
+        let stats = super::compute(&log.conversations(), &style);
+        assert_eq!(stats.messages, 6);
+        assert_eq!(stats.by_direction["sent"], 3);
+        assert_eq!(stats.by_direction["received"], 3);
+        assert_eq!(stats.by_contact[0].contact, "Alice");
+        assert_eq!(stats.by_contact[0].sent, 2);
+        assert_eq!(stats.by_month["2018-07"], 2);
+        assert_eq!(stats.by_month["2018-08"], 4);
+        // 2018-07-31 was a Tuesday
+        assert_eq!(stats.by_weekday[1], 2);
+        assert_eq!(stats.median_reply_seconds.received, Some(180));
+        assert_eq!(stats.median_reply_seconds.sent, Some(43200));
+        assert_eq!(stats.longest_gaps[0].seconds, 86340);
+        assert_eq!(
+            stats.longest_gaps[0].from.date_naive(),
+            NaiveDate::from_ymd_opt(2018, 7, 31).unwrap()
+        );
+        assert_eq!(stats.longest_streaks[0].days, 2);
+        assert_eq!(
+            stats.longest_streaks[0].first_day,
+            NaiveDate::from_ymd_opt(2018, 7, 31).unwrap()
+        );
+        assert_eq!(stats.average_body_length, 6.0);
+        assert_eq!(stats.mms_content_types["image/png"], 1);
+        assert_eq!(stats.attachment_bytes, 8);
+
+        // Seven hours behind, the same gap started the day before
+        let style = DateStyle {
+            timezone: DisplayTimezone::Named(chrono_tz::America::Los_Angeles),
+            ..Default::default()
+        };
+        let stats = super::compute(&log.conversations(), &style);
+        assert_eq!(
+            stats.longest_gaps[0].from.date_naive(),
+            NaiveDate::from_ymd_opt(2018, 7, 30).unwrap()
+        );
+    }
+}
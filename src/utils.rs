@@ -1,3 +1,18 @@
+/// Formats bytes as lowercase hex
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Formats a number of seconds like `1:02:03` or `2:03`
+pub fn format_duration(seconds: u64) -> String {
+    let (hours, minutes, seconds) = (seconds / 3600, (seconds / 60) % 60, seconds % 60);
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("{}:{:02}", minutes, seconds)
+    }
+}
+
 pub mod base64_opt {
     use base64::{engine::general_purpose::STANDARD as ENGINE, Engine};
     use serde::{de, Deserialize, Deserializer, Serializer};
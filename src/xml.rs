@@ -1,98 +1,556 @@
-use std::collections::HashSet;
-use std::fmt::{self, Debug};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::error::Error;
+use std::fmt::{self, Debug, Display};
+use std::io::{self, BufRead, Write};
 use std::str::FromStr;
 
 use base64::{engine::general_purpose::STANDARD as BASE64_ENGINE, Engine};
 use chrono::{DateTime, TimeZone, Utc};
+use itertools::Itertools;
 
-use markup5ever_rcdom::{Handle, Node, NodeData, RcDom};
-use xml5ever::driver::XmlParseOpts;
-use xml5ever::interface::TreeSink;
-use xml5ever::tendril::TendrilSink;
+use xml5ever::buffer_queue::BufferQueue;
+use xml5ever::tendril::StrTendril;
+use xml5ever::tokenizer::{TagKind, Token, TokenSink, XmlTokenizer, XmlTokenizerOpts};
 use xml5ever::Attribute as XmlAttribute;
 use xml5ever::QualName;
 
-use crate::model::{MessageKind, MmsMessage, MmsMessagePart, PhoneNumber, SmsMessage, TextLog};
+use crate::model::{
+    AddressRole, CallKind, CallLog, CallRecord, MessageKind, MmsAddress, MmsHeaders, MmsMessage,
+    MmsMessagePart, PhoneNumber, Presentation, SmsMessage, TextLog,
+};
+use crate::sanitize::EscapeCleaner;
 
-pub fn parse_log(verbose: bool, text: String) -> TextLog {
-    let mut opts = XmlParseOpts::default();
-    opts.tokenizer.exact_errors = verbose;
-    let parser = ::xml5ever::driver::parse_document(RcDom::default(), opts);
-    let mut dom = parser.one(text);
-    if verbose {
-        println!("Errors {:#?}", dom.errors);
-    }
-    let document = dom.get_document();
-    let element = document
-        .children
-        .borrow()
-        .iter()
-        .find_map(|node| element_contents(node))
-        .unwrap();
+/// Parses an entire XML backup
+///
+/// If `lenient` is set, invalid records are skipped (and summarized at the end)
+/// instead of failing the whole parse.
+pub fn parse_log<R: BufRead>(
+    verbose: bool,
+    lenient: bool,
+    reader: R,
+) -> Result<TextLog, ParseError> {
     let mut sms_messages = Vec::new();
     let mut mms_messages = Vec::new();
-
-    for sms in element.filter_elements("sms") {
-        sms_messages.push(parse_sms(&sms));
-    }
-    for mms in element.filter_elements("mms") {
-        mms_messages.push(parse_mms(&mms));
+    let mut ignored_calls = 0;
+    read_records(verbose, lenient, reader, |record| match record {
+        Record::Sms(sms) => sms_messages.push(sms),
+        Record::Mms(mms) => mms_messages.push(mms),
+        Record::Call(_) => ignored_calls += 1,
+    })?;
+    if ignored_calls > 0 {
+        log::warn!(
+            "Ignoring {} calls, which need the `calls` subcommands",
+            ignored_calls
+        );
     }
-    TextLog {
+    Ok(TextLog {
         sms_messages,
         mms_messages,
+        ..Default::default()
+    })
+}
+
+/// Parses an entire call log backup
+///
+/// This is just like [parse_log], except for `calls-*.xml` files.
+pub fn parse_call_log<R: BufRead>(
+    verbose: bool,
+    lenient: bool,
+    reader: R,
+) -> Result<CallLog, ParseError> {
+    let mut calls = Vec::new();
+    let mut ignored_messages = 0;
+    read_records(verbose, lenient, reader, |record| match record {
+        Record::Call(call) => calls.push(call),
+        Record::Sms(_) | Record::Mms(_) => ignored_messages += 1,
+    })?;
+    if ignored_messages > 0 {
+        log::warn!("Ignoring {} text messages in call log", ignored_messages);
     }
+    Ok(CallLog { calls })
 }
 
-fn parse_mms(element: &ElementData) -> MmsMessage {
-    let address = PhoneNumber(element.attr("address").into());
-    let date = parse_unix_epoch(element.attr("date"));
-    let readable_date = element.attr("readable_date").to_owned();
-    let contact_name = element.attr("contact_name").to_owned();
-    let msg_box = element.attr("msg_box");
-    let kind = match msg_box {
-        "2" => {
-            // sent
-            MessageKind::Sent
+/// Passes every record in the backup to `handle`,
+/// skipping invalid ones if `lenient` is set
+fn read_records<R: BufRead>(
+    verbose: bool,
+    lenient: bool,
+    reader: R,
+    mut handle: impl FnMut(Record),
+) -> Result<(), ParseError> {
+    let mut dropped = Vec::new();
+    for record in LogReader::new(verbose, reader) {
+        match record {
+            Ok(record) => handle(record),
+            Err(ParseError::InvalidRecord(error)) if lenient => {
+                if verbose {
+                    log::warn!("Skipping {}", error);
+                }
+                dropped.push(error);
+            }
+            Err(error) => return Err(error),
         }
-        "1" => {
-            // received
-            MessageKind::Received {
-                date_sent: parse_unix_epoch(element.attr("date_sent")),
+    }
+    if !dropped.is_empty() {
+        summarize_dropped(&dropped);
+    }
+    Ok(())
+}
+
+fn summarize_dropped(dropped: &[RecordError]) {
+    const MAX_LINES: usize = 10;
+    let mut by_reason = BTreeMap::<String, Vec<usize>>::new();
+    for error in dropped {
+        by_reason
+            .entry(format!("<{}>: {}", error.element, error.kind))
+            .or_default()
+            .push(error.line);
+    }
+    log::warn!("Dropped {} invalid records:", dropped.len());
+    for (reason, lines) in &by_reason {
+        let mut shown = lines
+            .iter()
+            .take(MAX_LINES)
+            .map(usize::to_string)
+            .join(", ");
+        if lines.len() > MAX_LINES {
+            shown.push_str(", ...");
+        }
+        let plural = if lines.len() == 1 { "line" } else { "lines" };
+        log::warn!("  {}x {} ({} {})", lines.len(), reason, plural, shown);
+    }
+}
+
+/// An error encountered while parsing an XML backup
+#[derive(Debug)]
+pub enum ParseError {
+    /// Failed to read the underlying file
+    Io(io::Error),
+    /// A single record in the backup was malformed
+    InvalidRecord(RecordError),
+}
+impl Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Io(_) => f.write_str("Unable to read backup"),
+            ParseError::InvalidRecord(cause) => Display::fmt(cause, f),
+        }
+    }
+}
+impl Error for ParseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ParseError::Io(cause) => Some(cause),
+            ParseError::InvalidRecord(_) => None,
+        }
+    }
+}
+impl From<io::Error> for ParseError {
+    fn from(cause: io::Error) -> Self {
+        ParseError::Io(cause)
+    }
+}
+impl From<RecordError> for ParseError {
+    fn from(cause: RecordError) -> Self {
+        ParseError::InvalidRecord(cause)
+    }
+}
+
+/// A malformed element in the XML backup
+#[derive(Debug)]
+pub struct RecordError {
+    /// The name of the offending element
+    pub element: String,
+    /// The (one-based) line number of the offending element
+    pub line: usize,
+    pub kind: RecordErrorKind,
+}
+impl Display for RecordError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Invalid <{}> on line {}: {}",
+            self.element, self.line, self.kind
+        )
+    }
+}
+impl Error for RecordError {}
+
+#[derive(Debug)]
+pub enum RecordErrorKind {
+    /// A required attribute wasn't present
+    MissingAttribute(&'static str),
+    /// An attribute had a value we couldn't understand
+    InvalidAttribute { name: &'static str, value: String },
+    /// A required child element wasn't present
+    MissingChild(&'static str),
+    /// A child element we didn't expect
+    UnexpectedChild(String),
+    /// The element was closed by a tag with a different name
+    MismatchedClosingTag(String),
+    /// The input ended before the element was closed
+    UnclosedElement,
+}
+impl Display for RecordErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordErrorKind::MissingAttribute(name) => write!(f, "missing attribute {:?}", name),
+            RecordErrorKind::InvalidAttribute { name, value } => {
+                write!(f, "invalid attribute {}={:?}", name, value)
+            }
+            RecordErrorKind::MissingChild(name) => write!(f, "missing child <{}>", name),
+            RecordErrorKind::UnexpectedChild(name) => write!(f, "unexpected child <{}>", name),
+            RecordErrorKind::MismatchedClosingTag(name) => write!(f, "closed by </{}>", name),
+            RecordErrorKind::UnclosedElement => {
+                f.write_str("never closed (the backup may be truncated)")
             }
         }
-        _ => panic!("Unknown message box {:?}", msg_box),
-    };
-    let parts = element.find_child("parts");
+    }
+}
+
+/// A single message (or call) parsed out of an XML backup
+#[derive(Clone, Debug)]
+#[allow(clippy::large_enum_variant)] // Records are consumed right away
+pub enum Record {
+    Sms(SmsMessage),
+    Mms(MmsMessage),
+    Call(CallRecord),
+}
+
+/// Incrementally parses the messages in an XML backup, one at a time
+///
+/// Only the record currently being parsed is kept in memory,
+/// so this works fine on backups that are many gigabytes.
+/// The utf16 surrogate escapes are fixed up on the fly,
+/// just like [crate::sanitize::cleanup_html_escapes].
+pub struct LogReader<R> {
+    reader: R,
+    tokenizer: XmlTokenizer<RecordSink>,
+    cleaner: EscapeCleaner,
+    line: String,
+    sanitized: String,
+    finished: bool,
+}
+impl<R: BufRead> LogReader<R> {
+    pub fn new(verbose: bool, reader: R) -> Self {
+        let opts = XmlTokenizerOpts {
+            exact_errors: verbose,
+            ..Default::default()
+        };
+        LogReader {
+            reader,
+            tokenizer: XmlTokenizer::new(RecordSink::new(verbose), opts),
+            cleaner: EscapeCleaner::default(),
+            line: String::new(),
+            sanitized: String::new(),
+            finished: false,
+        }
+    }
+    /// Feeds the next line of input into the tokenizer,
+    /// returning `false` once the input is exhausted.
+    fn feed_line(&mut self) -> io::Result<bool> {
+        self.line.clear();
+        self.sanitized.clear();
+        let eof = self.reader.read_line(&mut self.line)? == 0;
+        self.tokenizer.sink.line += 1;
+        if eof {
+            self.cleaner.finish(&mut self.sanitized);
+        } else {
+            self.cleaner.push(&self.line, &mut self.sanitized);
+        }
+        // Tags are fed one at a time, so the sink knows which line each one started on
+        // (a `<` can't appear anywhere else, since it's always escaped in text and attributes)
+        let mut remaining = &*self.sanitized;
+        while !remaining.is_empty() {
+            let end = remaining[1..]
+                .find('<')
+                .map_or(remaining.len(), |index| index + 1);
+            if remaining.starts_with('<') {
+                self.tokenizer.sink.tag_line = self.tokenizer.sink.line;
+            }
+            let mut queue = BufferQueue::new();
+            queue.push_back(StrTendril::from_slice(&remaining[..end]));
+            self.tokenizer.feed(&mut queue);
+            remaining = &remaining[end..];
+        }
+        if eof {
+            self.tokenizer.end();
+            let sink = &mut self.tokenizer.sink;
+            sink.finish();
+            if sink.verbose {
+                println!("Errors {:#?}", sink.errors);
+            }
+        }
+        Ok(!eof)
+    }
+}
+impl<R: BufRead> Iterator for LogReader<R> {
+    type Item = Result<Record, ParseError>;
+
+    fn next(&mut self) -> Option<Result<Record, ParseError>> {
+        loop {
+            if let Some(element) = self.tokenizer.sink.completed.pop_front() {
+                let element = match element {
+                    Ok(element) => element,
+                    Err(error) => return Some(Err(error.into())),
+                };
+                let record = match &*element.name {
+                    "sms" => parse_sms(&element).map(Record::Sms),
+                    "mms" => parse_mms(&element).map(Record::Mms),
+                    "call" => parse_call(&element).map(Record::Call),
+                    _ => continue,
+                };
+                return Some(record.map_err(ParseError::from));
+            }
+            if self.finished {
+                return None;
+            }
+            match self.feed_line() {
+                Ok(more) => self.finished = !more,
+                Err(cause) => {
+                    self.finished = true;
+                    return Some(Err(cause.into()));
+                }
+            }
+        }
+    }
+}
+
+/// Builds up the children of the root element (the individual records),
+/// discarding each one as soon as it's been consumed.
+struct RecordSink {
+    verbose: bool,
+    /// The line currently being fed to the tokenizer
+    line: usize,
+    /// The line the most recent tag started on
+    tag_line: usize,
+    /// The elements which are currently open, excluding the root
+    stack: Vec<ElementData>,
+    /// The name and line of the root element, while it's open
+    root: Option<(String, usize)>,
+    /// The first problem with the record that's currently open
+    malformed: Option<RecordError>,
+    completed: VecDeque<Result<ElementData, RecordError>>,
+    errors: Vec<Cow<'static, str>>,
+}
+impl RecordSink {
+    fn new(verbose: bool) -> Self {
+        RecordSink {
+            verbose,
+            line: 0,
+            tag_line: 0,
+            stack: Vec::new(),
+            root: None,
+            malformed: None,
+            completed: VecDeque::new(),
+            errors: Vec::new(),
+        }
+    }
+    fn open(&mut self, element: ElementData) {
+        if self.root.is_some() {
+            self.stack.push(element);
+        } else {
+            self.root = Some((element.name, element.line));
+        }
+    }
+    fn close(&mut self, name: &str) {
+        match self.stack.pop() {
+            Some(element) => {
+                if element.name != name && self.malformed.is_none() {
+                    self.malformed = Some(RecordError {
+                        element: element.name.clone(),
+                        line: element.line,
+                        kind: RecordErrorKind::MismatchedClosingTag(name.to_owned()),
+                    });
+                }
+                match self.stack.last_mut() {
+                    Some(parent) => parent.children.push(element),
+                    None => self.completed.push_back(match self.malformed.take() {
+                        Some(error) => Err(error),
+                        None => Ok(element),
+                    }),
+                }
+            }
+            // Closing the root
+            None => self.root = None,
+        }
+    }
+    /// Reports the record that was still open when the input ran out
+    ///
+    /// If no record was open, the backup might have been cut off partway through a tag,
+    /// so the root is reported instead.
+    fn finish(&mut self) {
+        let unclosed = match self.stack.first() {
+            Some(record) => Some((record.name.clone(), record.line)),
+            None => self.root.take(),
+        };
+        if let Some((element, line)) = unclosed {
+            self.completed.push_back(Err(RecordError {
+                element,
+                line,
+                kind: RecordErrorKind::UnclosedElement,
+            }));
+        }
+        self.stack.clear();
+        self.root = None;
+        self.malformed = None;
+    }
+}
+impl TokenSink for RecordSink {
+    fn process_token(&mut self, token: Token) {
+        match token {
+            Token::TagToken(tag) => {
+                let name = local_name(&tag.name);
+                match tag.kind {
+                    TagKind::StartTag => self.open(ElementData {
+                        name,
+                        line: self.tag_line,
+                        attrs: tag.attrs.iter().map(Attribute::from).collect(),
+                        children: Vec::new(),
+                    }),
+                    TagKind::EmptyTag => {
+                        self.open(ElementData {
+                            name: name.clone(),
+                            line: self.tag_line,
+                            attrs: tag.attrs.iter().map(Attribute::from).collect(),
+                            children: Vec::new(),
+                        });
+                        self.close(&name);
+                    }
+                    TagKind::EndTag | TagKind::ShortTag => self.close(&name),
+                }
+            }
+            Token::ParseError(error) if self.verbose => self.errors.push(error),
+            _ => {}
+        }
+    }
+}
+
+fn parse_mms(element: &ElementData) -> Result<MmsMessage, RecordError> {
+    let address = PhoneNumber::new(element.attr("address")?);
+    let date = parse_unix_epoch(element, "date")?;
+    let readable_date = element.attr("readable_date")?.to_owned();
+    let contact_name = element.attr("contact_name")?.to_owned();
+    let kind = parse_message_kind(element, "msg_box")?;
+    let parts = element.find_child("parts")?;
     let parts = parts
         .child_elements()
-        .map(|part| parse_mms_part(&part))
-        .collect::<Vec<MmsMessagePart>>();
-    MmsMessage {
+        .map(parse_mms_part)
+        .collect::<Result<Vec<MmsMessagePart>, RecordError>>()?;
+    // Older versions of the app didn't include the addresses
+    let addrs = match element.child_elements().find(|child| child.name == "addrs") {
+        Some(addrs) => addrs
+            .child_elements()
+            .map(parse_mms_addr)
+            .collect::<Result<Vec<MmsAddress>, RecordError>>()?,
+        None => Vec::new(),
+    };
+    let mut attrs = AttrReader::new(
+        element,
+        &[
+            "address",
+            "date",
+            "readable_date",
+            "contact_name",
+            "msg_box",
+        ],
+    );
+    attrs.date_sent(kind);
+    let headers = MmsHeaders {
+        sub: attrs.text("sub"),
+        sub_cs: attrs.int("sub_cs"),
+        m_id: attrs.text("m_id"),
+        tr_id: attrs.text("tr_id"),
+        ct_t: attrs.text("ct_t"),
+        ct_cls: attrs.int("ct_cls"),
+        ct_l: attrs.text("ct_l"),
+        m_cls: attrs.text("m_cls"),
+        m_type: attrs.int("m_type"),
+        v: attrs.int("v"),
+        m_size: attrs.int("m_size"),
+        pri: attrs.int("pri"),
+        rr: attrs.int("rr"),
+        read_status: attrs.int("read_status"),
+        d_rpt: attrs.int("d_rpt"),
+        rpt_a: attrs.int("rpt_a"),
+        d_tm: attrs.int("d_tm"),
+        exp: attrs.int("exp"),
+        st: attrs.int("st"),
+        retr_st: attrs.int("retr_st"),
+        retr_txt: attrs.text("retr_txt"),
+        retr_txt_cs: attrs.int("retr_txt_cs"),
+        resp_st: attrs.int("resp_st"),
+        resp_txt: attrs.text("resp_txt"),
+        creator: attrs.text("creator"),
+        sub_id: attrs.int("sub_id"),
+        read: attrs.flag("read"),
+        seen: attrs.flag("seen"),
+        locked: attrs.flag("locked"),
+        text_only: attrs.flag("text_only"),
+        callback_set: attrs.int("callback_set"),
+    };
+    Ok(MmsMessage {
         kind,
         date,
         readable_date,
         contact_name,
         address,
         parts,
+        addrs,
+        headers,
+        extra: attrs.extra(),
+    })
+}
+fn parse_mms_addr(element: &ElementData) -> Result<MmsAddress, RecordError> {
+    if element.name != "addr" {
+        return Err(element.error(RecordErrorKind::UnexpectedChild(element.name.clone())));
     }
+    let address = PhoneNumber::new(element.attr("address")?);
+    let role = element.attr("type")?;
+    let role = i32::from_str(role).map_err(|_| element.invalid_attr("type", role))?;
+    let mut attrs = AttrReader::new(element, &["address", "type"]);
+    Ok(MmsAddress {
+        address,
+        role: AddressRole::from_code(role),
+        charset: attrs.int("charset"),
+        extra: attrs.extra(),
+    })
 }
-fn parse_mms_part(element: &ElementData) -> MmsMessagePart {
-    assert_eq!(element.name, "part");
-    let content_type = element.attr("ct").to_owned();
-    let content_location = element.attr("cl").to_owned();
-    let text = parse_opt_text(element.attr("text"));
-    let seq = i32::from_str(element.attr("seq")).unwrap();
-    let data = element
-        .get_attr("data")
-        .map(|data| BASE64_ENGINE.decode(data).expect("Unable to base64 decode"));
-    MmsMessagePart {
+fn parse_mms_part(element: &ElementData) -> Result<MmsMessagePart, RecordError> {
+    if element.name != "part" {
+        return Err(element.error(RecordErrorKind::UnexpectedChild(element.name.clone())));
+    }
+    let content_type = element.attr("ct")?.to_owned();
+    let content_location = element.attr("cl")?.to_owned();
+    let text = parse_opt_text(element.attr("text")?);
+    let seq = element.attr("seq")?;
+    let seq = i32::from_str(seq).map_err(|_| element.invalid_attr("seq", seq))?;
+    let data = match element.get_attr("data") {
+        Some(data) => Some(
+            BASE64_ENGINE
+                .decode(data)
+                .map_err(|_| element.invalid_attr("data", data))?,
+        ),
+        None => None,
+    };
+    let mut attrs = AttrReader::new(element, &["ct", "cl", "text", "seq", "data"]);
+    Ok(MmsMessagePart {
         content_type,
         content_location,
         text,
         seq,
         data,
-    }
+        name: attrs.text("name"),
+        charset: attrs.int("chset"),
+        content_disposition: attrs.text("cd"),
+        filename: attrs.text("fn"),
+        content_id: attrs.text("cid"),
+        ctt_s: attrs.text("ctt_s"),
+        ctt_t: attrs.text("ctt_t"),
+        extra: attrs.extra(),
+        blob: None,
+    })
 }
 fn parse_opt_text(text: &str) -> Option<String> {
     if text == "null" {
@@ -101,69 +559,205 @@ fn parse_opt_text(text: &str) -> Option<String> {
         Some(text.into())
     }
 }
-fn parse_sms(element: &ElementData) -> SmsMessage {
-    let address = PhoneNumber(element.attr("address").into());
-    let date = parse_unix_epoch(element.attr("date"));
-    let body = element.attr("body").to_owned();
-    let readable_date = element.attr("readable_date").to_owned();
-    let contact_name = element.attr("contact_name").to_owned();
-    let msg_type = element.attr("type");
-    let kind = match msg_type {
-        "2" => {
-            // sent
-            MessageKind::Sent
-        }
-        "1" => {
-            // received
-            MessageKind::Received {
-                date_sent: parse_unix_epoch(element.attr("date_sent")),
-            }
-        }
-        _ => panic!("Unknown msg type {:?}", msg_type),
-    };
-    SmsMessage {
+fn parse_sms(element: &ElementData) -> Result<SmsMessage, RecordError> {
+    let address = PhoneNumber::new(element.attr("address")?);
+    let date = parse_unix_epoch(element, "date")?;
+    let body = element.attr("body")?.to_owned();
+    let readable_date = element.attr("readable_date")?.to_owned();
+    let contact_name = element.attr("contact_name")?.to_owned();
+    let kind = parse_message_kind(element, "type")?;
+    let mut attrs = AttrReader::new(
+        element,
+        &[
+            "address",
+            "date",
+            "body",
+            "readable_date",
+            "contact_name",
+            "type",
+        ],
+    );
+    attrs.date_sent(kind);
+    Ok(SmsMessage {
         kind,
         date,
         body,
         readable_date,
         contact_name,
         address,
-    }
+        protocol: attrs.int("protocol"),
+        subject: attrs.text("subject"),
+        toa: attrs.text("toa"),
+        sc_toa: attrs.text("sc_toa"),
+        service_center: attrs.text("service_center"),
+        read: attrs.flag("read"),
+        status: attrs.int("status"),
+        locked: attrs.flag("locked"),
+        sub_id: attrs.int("sub_id"),
+        extra: attrs.extra(),
+    })
+}
+fn parse_call(element: &ElementData) -> Result<CallRecord, RecordError> {
+    let number = PhoneNumber::new(element.attr("number")?);
+    let duration = element.attr("duration")?;
+    let duration =
+        u64::from_str(duration).map_err(|_| element.invalid_attr("duration", duration))?;
+    let date = parse_unix_epoch(element, "date")?;
+    let kind = element.attr("type")?;
+    let kind = i32::from_str(kind)
+        .ok()
+        .and_then(CallKind::from_code)
+        .ok_or_else(|| element.invalid_attr("type", kind))?;
+    let presentation = element.attr("presentation")?;
+    let presentation = i32::from_str(presentation)
+        .map_err(|_| element.invalid_attr("presentation", presentation))?;
+    let readable_date = element.attr("readable_date")?.to_owned();
+    let contact_name = element.attr("contact_name")?.to_owned();
+    let attrs = AttrReader::new(
+        element,
+        &[
+            "number",
+            "duration",
+            "date",
+            "type",
+            "presentation",
+            "readable_date",
+            "contact_name",
+        ],
+    );
+    Ok(CallRecord {
+        number,
+        duration,
+        date,
+        kind,
+        presentation: Presentation::from_code(presentation),
+        contact_name,
+        readable_date,
+        extra: attrs.extra(),
+    })
 }
-fn parse_unix_epoch(date: &str) -> DateTime<Utc> {
+/// Parses the message box (`type` for SMS and `msg_box` for MMS)
+fn parse_message_kind(
+    element: &ElementData,
+    attr: &'static str,
+) -> Result<MessageKind, RecordError> {
+    let msg_box = element.attr(attr)?;
+    Ok(match msg_box {
+        "1" => {
+            // received
+            MessageKind::Received {
+                date_sent: parse_unix_epoch(element, "date_sent")?,
+            }
+        }
+        "2" => MessageKind::Sent,
+        "3" => MessageKind::Draft,
+        "4" => MessageKind::Outbox,
+        "5" => MessageKind::Failed,
+        "6" => MessageKind::Queued,
+        _ => return Err(element.invalid_attr(attr, msg_box)),
+    })
+}
+fn parse_unix_epoch(
+    element: &ElementData,
+    attr: &'static str,
+) -> Result<DateTime<Utc>, RecordError> {
+    let date = element.attr(attr)?;
     i64::from_str(date)
         .ok()
         .and_then(|val| Utc.timestamp_millis_opt(val).single())
-        .unwrap_or_else(|| panic!("Invalid digits in {:?}", date))
+        .ok_or_else(|| element.invalid_attr(attr, date))
+}
+
+/// Reads the optional attributes of an element
+///
+/// Unlike [ElementData::attr], these never fail. Anything which is unknown
+/// (or can't be parsed) is kept in [AttrReader::extra] instead,
+/// so that it can be written back out unchanged.
+struct AttrReader<'a> {
+    element: &'a ElementData,
+    /// The attributes which have been handled elsewhere
+    understood: Vec<&'static str>,
+}
+impl<'a> AttrReader<'a> {
+    fn new(element: &'a ElementData, required: &[&'static str]) -> Self {
+        AttrReader {
+            element,
+            understood: required.to_vec(),
+        }
+    }
+    fn text(&mut self, name: &'static str) -> Option<String> {
+        let value = self.element.get_attr(name)?;
+        self.understood.push(name);
+        parse_opt_text(value)
+    }
+    fn int<T: FromStr>(&mut self, name: &'static str) -> Option<T> {
+        let value = self.element.get_attr(name)?;
+        if value == "null" {
+            self.understood.push(name);
+            return None;
+        }
+        let result = T::from_str(value).ok();
+        if result.is_some() {
+            self.understood.push(name);
+        }
+        result
+    }
+    fn flag(&mut self, name: &'static str) -> bool {
+        let result = match self.element.get_attr(name) {
+            Some("1") => true,
+            Some("0") => false,
+            _ => return false,
+        };
+        self.understood.push(name);
+        result
+    }
+    /// Handles `date_sent`, which is part of [MessageKind::Received]
+    /// and usually zero for everything else
+    fn date_sent(&mut self, kind: MessageKind) {
+        match kind {
+            MessageKind::Received { .. } => self.understood.push("date_sent"),
+            _ if self.element.get_attr("date_sent") == Some("0") => {
+                self.understood.push("date_sent")
+            }
+            _ => {}
+        }
+    }
+    fn extra(self) -> BTreeMap<String, String> {
+        self.element
+            .attrs
+            .iter()
+            .filter(|attr| !self.understood.contains(&&*attr.name))
+            .map(|attr| (attr.name.clone(), attr.value.clone()))
+            .collect()
+    }
 }
 
 struct ElementData {
     name: String,
+    /// The line the element's start tag was found on
+    line: usize,
     attrs: Vec<Attribute>,
-    children: Vec<Handle>,
+    children: Vec<ElementData>,
 }
 impl ElementData {
-    fn child_elements(&self) -> impl Iterator<Item = ElementData> + '_ {
-        self.children
-            .iter()
-            .filter_map(|node| element_contents(node))
+    fn child_elements(&self) -> impl Iterator<Item = &ElementData> + '_ {
+        self.children.iter()
     }
     #[inline]
-    fn find_child(&self, name: &str) -> ElementData {
-        self.filter_elements(name).next().unwrap()
-    }
-    fn filter_elements<'a>(&'a self, name: &'a str) -> impl Iterator<Item = ElementData> + 'a {
+    fn find_child(&self, name: &'static str) -> Result<&ElementData, RecordError> {
         self.child_elements()
-            .filter(move |element| element.name == name)
+            .find(|element| element.name == name)
+            .ok_or_else(|| self.error(RecordErrorKind::MissingChild(name)))
     }
-    fn child_name_set(&self) -> HashSet<String> {
+    fn child_name_set(&self) -> HashSet<&str> {
         self.child_elements()
-            .map(|element| element.name)
+            .map(|element| &*element.name)
             .collect::<HashSet<_>>()
     }
     #[inline]
-    fn attr(&self, name: &str) -> &str {
-        self.get_attr(name).unwrap()
+    fn attr(&self, name: &'static str) -> Result<&str, RecordError> {
+        self.get_attr(name)
+            .ok_or_else(|| self.error(RecordErrorKind::MissingAttribute(name)))
     }
     fn get_attr(&self, name: &str) -> Option<&str> {
         self.attrs
@@ -171,11 +765,25 @@ impl ElementData {
             .find(|attr| attr.name == name)
             .map(|attr| &*attr.value)
     }
+    fn invalid_attr(&self, name: &'static str, value: &str) -> RecordError {
+        self.error(RecordErrorKind::InvalidAttribute {
+            name,
+            value: value.into(),
+        })
+    }
+    fn error(&self, kind: RecordErrorKind) -> RecordError {
+        RecordError {
+            element: self.name.clone(),
+            line: self.line,
+            kind,
+        }
+    }
 }
 impl Debug for ElementData {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("ElementData")
             .field("name", &self.name)
+            .field("line", &self.line)
             .field("attrs", &self.attrs)
             .field("child_names", &self.child_name_set())
             .finish()
@@ -197,19 +805,589 @@ impl<'a> From<&'a XmlAttribute> for Attribute {
 fn local_name(name: &QualName) -> String {
     String::from(&*name.local)
 }
-fn element_contents(node: &Node) -> Option<ElementData> {
-    if let NodeData::Element {
-        ref name,
-        ref attrs,
-        ..
-    } = node.data
-    {
-        Some(ElementData {
-            name: local_name(name),
-            attrs: attrs.borrow().iter().map(Attribute::from).collect(),
-            children: node.children.borrow().clone(),
-        })
-    } else {
-        None
+
+/// Writes a log in the same format as SMS Backup & Restore,
+/// so that it can be restored back onto a phone.
+///
+/// Messages are written in chronological order.
+pub fn write_log<W: Write>(log: &TextLog, mut out: W) -> io::Result<()> {
+    let mut records = log
+        .sms_messages
+        .iter()
+        .map(|sms| (sms.date, Ok(sms)))
+        .chain(log.mms_messages.iter().map(|mms| (mms.date, Err(mms))))
+        .collect::<Vec<_>>();
+    records.sort_by_key(|&(date, _)| date);
+    writeln!(
+        out,
+        "<?xml version='1.0' encoding='UTF-8' standalone='yes' ?>"
+    )?;
+    writeln!(
+        out,
+        "<!--File Created By {} v{}-->",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION")
+    )?;
+    writeln!(out, "<smses count=\"{}\">", records.len())?;
+    for (_, record) in records {
+        match record {
+            Ok(sms) => write_sms(sms, &mut out)?,
+            Err(mms) => write_mms(mms, &mut out)?,
+        }
+    }
+    writeln!(out, "</smses>")?;
+    out.flush()
+}
+/// Writes a call log in the same format as SMS Backup & Restore
+///
+/// Calls are written in chronological order.
+pub fn write_call_log<W: Write>(log: &CallLog, mut out: W) -> io::Result<()> {
+    let mut calls = log.calls.iter().collect::<Vec<_>>();
+    calls.sort_by_key(|call| call.date);
+    writeln!(
+        out,
+        "<?xml version='1.0' encoding='UTF-8' standalone='yes' ?>"
+    )?;
+    writeln!(
+        out,
+        "<!--File Created By {} v{}-->",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION")
+    )?;
+    writeln!(out, "<calls count=\"{}\">", calls.len())?;
+    for call in calls {
+        let mut attrs = AttrWriter::default();
+        attrs.push("number", call.number.raw());
+        attrs.push("duration", call.duration.to_string());
+        attrs.push("date", call.date.timestamp_millis().to_string());
+        attrs.push("type", call.kind.code().to_string());
+        attrs.push("presentation", call.presentation.code().to_string());
+        attrs.push("readable_date", &call.readable_date);
+        attrs.push("contact_name", &call.contact_name);
+        attrs.extra(&call.extra);
+        attrs.write_tag(&mut out, 1, "call", true)?;
+    }
+    writeln!(out, "</calls>")?;
+    out.flush()
+}
+fn write_sms<W: Write>(sms: &SmsMessage, out: &mut W) -> io::Result<()> {
+    let mut attrs = AttrWriter::default();
+    attrs.int("protocol", sms.protocol);
+    attrs.push("address", sms.address.raw());
+    attrs.push("date", sms.date.timestamp_millis().to_string());
+    attrs.push("type", message_kind_code(sms.kind));
+    attrs.text("subject", &sms.subject);
+    attrs.push("body", &sms.body);
+    attrs.text("toa", &sms.toa);
+    attrs.text("sc_toa", &sms.sc_toa);
+    attrs.text("service_center", &sms.service_center);
+    attrs.flag("read", sms.read);
+    attrs.int("status", sms.status);
+    attrs.flag("locked", sms.locked);
+    attrs.date_sent(sms.kind);
+    attrs.int("sub_id", sms.sub_id);
+    attrs.push("readable_date", &sms.readable_date);
+    attrs.push("contact_name", &sms.contact_name);
+    attrs.extra(&sms.extra);
+    attrs.write_tag(out, 1, "sms", true)
+}
+fn write_mms<W: Write>(mms: &MmsMessage, out: &mut W) -> io::Result<()> {
+    let headers = &mms.headers;
+    let mut attrs = AttrWriter::default();
+    attrs.push("date", mms.date.timestamp_millis().to_string());
+    attrs.int("rr", headers.rr);
+    attrs.text("sub", &headers.sub);
+    attrs.text("ct_t", &headers.ct_t);
+    attrs.int("read_status", headers.read_status);
+    attrs.flag("seen", headers.seen);
+    attrs.push("msg_box", message_kind_code(mms.kind));
+    attrs.push("address", mms.address.raw());
+    attrs.int("sub_cs", headers.sub_cs);
+    attrs.int("resp_st", headers.resp_st);
+    attrs.int("retr_st", headers.retr_st);
+    attrs.int("d_tm", headers.d_tm);
+    attrs.flag("text_only", headers.text_only);
+    attrs.int("exp", headers.exp);
+    attrs.flag("locked", headers.locked);
+    attrs.text("m_id", &headers.m_id);
+    attrs.int("st", headers.st);
+    attrs.int("retr_txt_cs", headers.retr_txt_cs);
+    attrs.text("retr_txt", &headers.retr_txt);
+    attrs.text("creator", &headers.creator);
+    attrs.date_sent(mms.kind);
+    attrs.flag("read", headers.read);
+    attrs.int("m_size", headers.m_size);
+    attrs.int("rpt_a", headers.rpt_a);
+    attrs.int("ct_cls", headers.ct_cls);
+    attrs.int("pri", headers.pri);
+    attrs.int("sub_id", headers.sub_id);
+    attrs.text("tr_id", &headers.tr_id);
+    attrs.text("resp_txt", &headers.resp_txt);
+    attrs.text("ct_l", &headers.ct_l);
+    attrs.text("m_cls", &headers.m_cls);
+    attrs.int("d_rpt", headers.d_rpt);
+    attrs.int("v", headers.v);
+    attrs.int("m_type", headers.m_type);
+    attrs.int("callback_set", headers.callback_set);
+    attrs.push("readable_date", &mms.readable_date);
+    attrs.push("contact_name", &mms.contact_name);
+    attrs.extra(&mms.extra);
+    attrs.write_tag(out, 1, "mms", false)?;
+    writeln!(out, "    <parts>")?;
+    for part in &mms.parts {
+        let mut attrs = AttrWriter::default();
+        attrs.push("seq", part.seq.to_string());
+        attrs.push("ct", &part.content_type);
+        attrs.text("name", &part.name);
+        attrs.int("chset", part.charset);
+        attrs.text("cd", &part.content_disposition);
+        attrs.text("fn", &part.filename);
+        attrs.text("cid", &part.content_id);
+        attrs.push("cl", &part.content_location);
+        attrs.text("ctt_s", &part.ctt_s);
+        attrs.text("ctt_t", &part.ctt_t);
+        attrs.text("text", &part.text);
+        if let Some(data) = &part.data {
+            attrs.push("data", BASE64_ENGINE.encode(data));
+        }
+        attrs.extra(&part.extra);
+        attrs.write_tag(out, 3, "part", true)?;
+    }
+    writeln!(out, "    </parts>")?;
+    writeln!(out, "    <addrs>")?;
+    for addr in &mms.addrs {
+        let mut attrs = AttrWriter::default();
+        attrs.push("address", addr.address.raw());
+        attrs.push("type", addr.role.code().to_string());
+        attrs.int("charset", addr.charset);
+        attrs.extra(&addr.extra);
+        attrs.write_tag(out, 3, "addr", true)?;
+    }
+    writeln!(out, "    </addrs>")?;
+    writeln!(out, "  </mms>")
+}
+/// The inverse of [parse_message_kind]
+fn message_kind_code(kind: MessageKind) -> &'static str {
+    match kind {
+        MessageKind::Received { .. } => "1",
+        MessageKind::Sent => "2",
+        MessageKind::Draft => "3",
+        MessageKind::Outbox => "4",
+        MessageKind::Failed => "5",
+        MessageKind::Queued => "6",
+    }
+}
+
+/// Accumulates the attributes of an element, in order
+///
+/// This is the inverse of [AttrReader], writing `null` for missing values
+/// just like the app does.
+#[derive(Default)]
+struct AttrWriter<'a> {
+    attrs: Vec<(&'a str, Cow<'a, str>)>,
+}
+impl<'a> AttrWriter<'a> {
+    fn push(&mut self, name: &'a str, value: impl Into<Cow<'a, str>>) {
+        self.attrs.push((name, value.into()));
+    }
+    fn text(&mut self, name: &'a str, value: &'a Option<String>) {
+        self.push(name, value.as_deref().unwrap_or("null"));
+    }
+    fn int<T: ToString>(&mut self, name: &'a str, value: Option<T>) {
+        match value {
+            Some(value) => self.push(name, value.to_string()),
+            None => self.push(name, "null"),
+        }
+    }
+    fn flag(&mut self, name: &'a str, value: bool) {
+        self.push(name, if value { "1" } else { "0" });
+    }
+    fn date_sent(&mut self, kind: MessageKind) {
+        match kind {
+            MessageKind::Received { date_sent } => {
+                self.push("date_sent", date_sent.timestamp_millis().to_string())
+            }
+            // Overridden by `extra` if the original had something else
+            _ => self.push("date_sent", "0"),
+        }
+    }
+    /// Adds the attributes we didn't understand,
+    /// overriding any that we wrote in their place.
+    fn extra(&mut self, extra: &'a BTreeMap<String, String>) {
+        for (name, value) in extra {
+            match self.attrs.iter_mut().find(|(existing, _)| existing == name) {
+                Some((_, existing)) => *existing = Cow::Borrowed(value),
+                None => self.push(name, &**value),
+            }
+        }
+    }
+    fn write_tag<W: Write>(
+        &self,
+        out: &mut W,
+        indent: usize,
+        name: &str,
+        self_closing: bool,
+    ) -> io::Result<()> {
+        let mut tag = format!("{:indent$}<{}", "", name, indent = indent * 2);
+        for (name, value) in &self.attrs {
+            tag.push(' ');
+            tag.push_str(name);
+            tag.push_str("=\"");
+            escape_attr(value, &mut tag);
+            tag.push('"');
+        }
+        tag.push_str(if self_closing { " />" } else { ">" });
+        writeln!(out, "{}", tag)
+    }
+}
+/// Escapes an attribute value the same way the app does
+///
+/// Characters outside the BMP are written as a pair of escaped utf16 surrogates,
+/// which is why we need [crate::sanitize] when reading them back in.
+fn escape_attr(value: &str, out: &mut String) {
+    for c in value.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            _ if c.is_control() => out.push_str(&format!("&#{};", c as u32)),
+            _ if c.len_utf16() > 1 => {
+                let mut buffer = [0u16; 2];
+                for unit in c.encode_utf16(&mut buffer).iter() {
+                    out.push_str(&format!("&#{};", unit));
+                }
+            }
+            _ => out.push(c),
+        }
+    }
+}
+
+/// Builds backups for tests, out of just the records each test needs
+#[cfg(test)]
+pub mod testing {
+    use chrono::NaiveDateTime;
+
+    use crate::model::TextLog;
+
+    /// Parses the records as a backup, failing the test if any of them are malformed
+    pub fn log<R: AsRef<str>>(records: impl IntoIterator<Item = R>) -> TextLog {
+        let mut backup = String::from("<smses>\n");
+        for record in records {
+            backup.push_str("  ");
+            backup.push_str(record.as_ref());
+            backup.push('\n');
+        }
+        backup.push_str("</smses>\n");
+        super::parse_log(false, false, backup.as_bytes()).unwrap()
+    }
+
+    /// A text we sent to `address` on `date` (like `2018-07-31 01:20`, in UTC)
+    pub fn sent(address: &str, contact_name: &str, date: &str, body: &str) -> String {
+        sms(address, contact_name, date, 2, body)
+    }
+
+    /// A text we received from `address` on `date` (like `2018-07-31 01:20`, in UTC)
+    pub fn received(address: &str, contact_name: &str, date: &str, body: &str) -> String {
+        sms(address, contact_name, date, 1, body)
+    }
+
+    fn sms(address: &str, contact_name: &str, date: &str, kind: i32, body: &str) -> String {
+        format!(
+            r#"<sms address="{}" date="{}" type="{}" date_sent="0" body="{}" readable_date="" contact_name="{}" />"#,
+            escape(address),
+            millis(date),
+            kind,
+            escape(body),
+            escape(contact_name),
+        )
+    }
+
+    /// An MMS we sent to `address` (with a `~` between each number, for a group)
+    pub fn sent_mms(address: &str, contact_name: &str, date: &str, parts: &[String]) -> String {
+        mms(address, contact_name, date, 2, parts, &[])
+    }
+
+    /// An MMS we received from `address` (with a `~` between each number, for a group)
+    ///
+    /// The sender of a group message is the [addr_from] among the `addrs`.
+    pub fn received_mms(
+        address: &str,
+        contact_name: &str,
+        date: &str,
+        parts: &[String],
+        addrs: &[String],
+    ) -> String {
+        mms(address, contact_name, date, 1, parts, addrs)
+    }
+
+    fn mms(
+        address: &str,
+        contact_name: &str,
+        date: &str,
+        msg_box: i32,
+        parts: &[String],
+        addrs: &[String],
+    ) -> String {
+        let mut mms = format!(
+            r#"<mms address="{}" date="{}" msg_box="{}" date_sent="0" readable_date="" contact_name="{}">"#,
+            escape(address),
+            millis(date),
+            msg_box,
+            escape(contact_name),
+        );
+        mms.push_str(&format!("<parts>{}</parts>", parts.concat()));
+        // Older backups don't include the addresses
+        if !addrs.is_empty() {
+            mms.push_str(&format!("<addrs>{}</addrs>", addrs.concat()));
+        }
+        mms.push_str("</mms>");
+        mms
+    }
+
+    /// A `text/plain` part of an MMS
+    pub fn text_part(text: &str) -> String {
+        format!(
+            r#"<part seq="0" ct="text/plain" cl="text_0.txt" text="{}" />"#,
+            escape(text)
+        )
+    }
+
+    /// An attachment to an MMS, with its `data` in base64
+    pub fn data_part(content_type: &str, name: &str, data: &str) -> String {
+        format!(
+            r#"<part seq="0" ct="{}" cl="{}" text="null" data="{}" />"#,
+            escape(content_type),
+            escape(name),
+            data,
+        )
+    }
+
+    /// The sender of a group MMS
+    pub fn addr_from(address: &str) -> String {
+        format!(r#"<addr address="{}" type="137" />"#, escape(address))
+    }
+
+    /// A recipient of a group MMS
+    pub fn addr_to(address: &str) -> String {
+        format!(r#"<addr address="{}" type="151" />"#, escape(address))
+    }
+
+    fn millis(date: &str) -> i64 {
+        NaiveDateTime::parse_from_str(date, "%Y-%m-%d %H:%M")
+            .unwrap()
+            .and_utc()
+            .timestamp_millis()
+    }
+
+    fn escape(value: &str) -> String {
+        let mut escaped = String::new();
+        super::escape_attr(value, &mut escaped);
+        escaped
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        parse_call_log, parse_log, write_call_log, write_log, LogReader, ParseError, Record,
+        RecordErrorKind,
+    };
+    use crate::model::{CallKind, MessageKind, Presentation};
+
+    const BACKUP: &str = r#"<?xml version='1.0' encoding='UTF-8' standalone='yes' ?>
+<smses count="2">
+  <sms address="+15551234567" date="1533000000000" type="2" body="Whoop &#55357;&#56842;" readable_date="Jul 30, 2018 9:20:00 PM" contact_name="Alice" />
+  <mms address="+15551234567" date="1533000060000" msg_box="1" date_sent="1533000050000" readable_date="Jul 30, 2018 9:21:00 PM" contact_name="Alice">
+    <parts>
+      <part seq="0" ct="text/plain" cl="text_0.txt" text="multi
+line" />
+    </parts>
+  </mms>
+</smses>
+"#;
+
+    #[test]
+    fn test_streaming() {
+        let records = LogReader::new(false, BACKUP.as_bytes())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(records.len(), 2);
+        match &records[0] {
+            Record::Sms(sms) => {
+                assert_eq!(sms.body, "Whoop \u{1F60A}");
+                assert_eq!(sms.kind, MessageKind::Sent);
+            }
+            other => panic!("Unexpected record: {:?}", other),
+        }
+        match &records[1] {
+            Record::Mms(mms) => {
+                assert_eq!(mms.parts.len(), 1);
+                assert_eq!(mms.parts[0].text.as_deref(), Some("multi\nline"));
+            }
+            other => panic!("Unexpected record: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_located_errors() {
+        let backup = BACKUP.replace(r#"type="2""#, r#"type="9""#);
+        let mut records = LogReader::new(false, backup.as_bytes());
+        match records.next() {
+            Some(Err(ParseError::InvalidRecord(error))) => {
+                assert_eq!(error.element, "sms");
+                assert_eq!(error.line, 3);
+                assert!(matches!(
+                    error.kind,
+                    RecordErrorKind::InvalidAttribute { name: "type", .. }
+                ));
+            }
+            other => panic!("Unexpected result: {:?}", other),
+        }
+        // The rest of the records are unaffected
+        assert!(matches!(records.next(), Some(Ok(Record::Mms(_)))));
+        assert!(records.next().is_none());
+
+        // Errors are reported on the line the start tag began on
+        let backup = backup.replace(r#" type="9" "#, "\n    type=\"9\"\n    ");
+        match LogReader::new(false, backup.as_bytes()).next() {
+            Some(Err(ParseError::InvalidRecord(error))) => {
+                assert_eq!(error.element, "sms");
+                assert_eq!(error.line, 3);
+            }
+            other => panic!("Unexpected result: {:?}", other),
+        }
+        let backup = backup.replace("msg_box=\"1\"", "msg_box=\"9\"");
+        match LogReader::new(false, backup.as_bytes()).nth(1) {
+            Some(Err(ParseError::InvalidRecord(error))) => {
+                assert_eq!(error.element, "mms");
+                assert_eq!(error.line, 6);
+            }
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_mismatched_tags() {
+        let backup = BACKUP.replace("</parts>", "</part>");
+        let mut records = LogReader::new(false, backup.as_bytes());
+        assert!(matches!(records.next(), Some(Ok(Record::Sms(_)))));
+        match records.next() {
+            Some(Err(ParseError::InvalidRecord(error))) => {
+                assert_eq!(error.element, "parts");
+                assert_eq!(error.line, 5);
+                assert!(matches!(
+                    error.kind,
+                    RecordErrorKind::MismatchedClosingTag(ref name) if name == "part"
+                ));
+            }
+            other => panic!("Unexpected result: {:?}", other),
+        }
+        assert!(records.next().is_none());
+        let backup = BACKUP.replace(
+            r#"contact_name="Alice" />"#,
+            r#"contact_name="Alice"></mms>"#,
+        );
+        let log = parse_log(false, true, backup.as_bytes()).unwrap();
+        assert_eq!(log.sms_messages.len(), 0);
+        assert_eq!(log.mms_messages.len(), 1);
+    }
+
+    #[test]
+    fn test_truncated() {
+        // Cut off partway through the <mms>
+        let end = BACKUP.find("</parts>").unwrap();
+        let truncated = &BACKUP[..end];
+        let mut records = LogReader::new(false, truncated.as_bytes());
+        assert!(matches!(records.next(), Some(Ok(Record::Sms(_)))));
+        match records.next() {
+            Some(Err(ParseError::InvalidRecord(error))) => {
+                assert_eq!(error.element, "mms");
+                assert_eq!(error.line, 4);
+                assert!(matches!(error.kind, RecordErrorKind::UnclosedElement));
+            }
+            other => panic!("Unexpected result: {:?}", other),
+        }
+        assert!(records.next().is_none());
+        assert!(parse_log(false, false, truncated.as_bytes()).is_err());
+        let log = parse_log(false, true, truncated.as_bytes()).unwrap();
+        assert_eq!(log.sms_messages.len(), 1);
+
+        // Cut off partway through a tag
+        let end = BACKUP.find("9:21:00 PM").unwrap();
+        let log = parse_log(false, true, &BACKUP.as_bytes()[..end]).unwrap();
+        assert_eq!(log.sms_messages.len(), 1);
+        assert!(parse_log(false, false, &BACKUP.as_bytes()[..end]).is_err());
+
+        // Cut off after the last record
+        let end = BACKUP.find("</smses>").unwrap();
+        match parse_log(false, false, &BACKUP.as_bytes()[..end]) {
+            Err(ParseError::InvalidRecord(error)) => {
+                assert_eq!((&*error.element, error.line), ("smses", 2));
+                assert!(matches!(error.kind, RecordErrorKind::UnclosedElement));
+            }
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_unknown_attributes() {
+        let backup = BACKUP.replace(
+            r#"type="2""#,
+            r#"type="2" read="1" sub_id="oops" spam_report="0""#,
+        );
+        match LogReader::new(false, backup.as_bytes()).next() {
+            Some(Ok(Record::Sms(sms))) => {
+                assert!(sms.read);
+                assert_eq!(sms.sub_id, None);
+                assert_eq!(
+                    sms.extra.into_iter().collect::<Vec<_>>(),
+                    vec![
+                        ("spam_report".to_owned(), "0".to_owned()),
+                        ("sub_id".to_owned(), "oops".to_owned()),
+                    ]
+                );
+            }
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let backup = BACKUP.replace("multi", "<multi> \"&\" \u{1F60A} caf\u{E9}");
+        let log = parse_log(false, false, backup.as_bytes()).unwrap();
+        let mut written = Vec::new();
+        write_log(&log, &mut written).unwrap();
+        let written = String::from_utf8(written).unwrap();
+        assert!(written.contains("&#55357;&#56842;"), "{}", written);
+        let reparsed = parse_log(false, false, written.as_bytes()).unwrap();
+        assert_eq!(reparsed.sms_messages, log.sms_messages);
+        assert_eq!(reparsed.mms_messages, log.mms_messages);
+    }
+
+    const CALLS: &str = r#"<?xml version='1.0' encoding='UTF-8' standalone='yes' ?>
+<calls count="2">
+  <call number="+15551234567" duration="125" date="1533000000000" type="1" presentation="1" subscription_id="1" readable_date="Jul 30, 2018 9:20:00 PM" contact_name="Alice" />
+  <call number="" duration="0" date="1533000060000" type="3" presentation="2" readable_date="Jul 30, 2018 9:21:00 PM" contact_name="(Unknown)" />
+</calls>
+"#;
+
+    #[test]
+    fn test_calls() {
+        let log = parse_call_log(false, false, CALLS.as_bytes()).unwrap();
+        assert_eq!(log.calls.len(), 2);
+        assert_eq!(log.calls[0].kind, CallKind::Incoming);
+        assert_eq!(log.calls[0].duration, 125);
+        assert_eq!(log.calls[0].extra["subscription_id"], "1");
+        assert_eq!(log.calls[1].kind, CallKind::Missed);
+        assert_eq!(log.calls[1].presentation, Presentation::Restricted);
+        let mut written = Vec::new();
+        write_call_log(&log, &mut written).unwrap();
+        let reparsed = parse_call_log(false, false, &written[..]).unwrap();
+        assert_eq!(reparsed.calls, log.calls);
+        // Calls aren't text messages
+        let texts = parse_log(false, false, CALLS.as_bytes()).unwrap();
+        assert!(texts.sms_messages.is_empty());
     }
 }